@@ -0,0 +1,233 @@
+//this drives the configure, build and install phases of a package for each of the supported buildtypes
+use super::{
+    buildconfig::{BuildConfig, BuildType},
+    progress::Progress,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// out of tree build directory used by cmake and meson, relative to the source directory
+const BUILD_DIR: &str = "faebuild-build";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    Configure,
+    Build,
+    Install,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Configure => write!(f, "configure"),
+            Phase::Build => write!(f, "build"),
+            Phase::Install => write!(f, "install"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Step {
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+impl Step {
+    fn new(program: &str, args: &[&str]) -> Self {
+        Step {
+            program: program.to_owned(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env: vec![],
+        }
+    }
+
+    fn shell(cmd: &str) -> Self {
+        Step::new("sh", &["-c", cmd])
+    }
+
+    fn args(mut self, args: &[String]) -> Self {
+        self.args.extend_from_slice(args);
+        self
+    }
+
+    fn env(mut self, key: &str, value: &Path) -> Self {
+        self.env
+            .push((key.to_owned(), value.to_string_lossy().to_string()));
+        self
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.program == "sh" && self.args.len() == 2 {
+            return write!(f, "{}", self.args[1]);
+        }
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Builder<'a> {
+    config: &'a BuildConfig,
+    srcdir: PathBuf,
+    progress: &'a dyn Progress,
+}
+
+impl<'a> Builder<'a> {
+    pub fn new(
+        config: &'a BuildConfig,
+        workdir: &Path,
+        progress: &'a dyn Progress,
+    ) -> Result<Self> {
+        let srcdir = match &config.subdir {
+            Some(subdir) => workdir.join(subdir),
+            None => workdir.to_owned(),
+        };
+        if !srcdir.is_dir() {
+            return Err(anyhow!(
+                "source directory {} does not exist, is subdir correct?",
+                srcdir.display()
+            ));
+        }
+        Ok(Builder {
+            config,
+            srcdir,
+            progress,
+        })
+    }
+
+    pub fn configure(&self) -> Result<()> {
+        let configopts = self.config.configopts.clone().unwrap_or_default();
+        let steps = match self.config.buildtype {
+            BuildType::Simple => vec![],
+            BuildType::Cmake => vec![Step::new(
+                "cmake",
+                &[
+                    "-B",
+                    BUILD_DIR,
+                    "-DCMAKE_INSTALL_PREFIX=/usr",
+                    "-DCMAKE_BUILD_TYPE=Release",
+                ],
+            )
+            .args(&configopts)],
+            BuildType::CmakeNinja => vec![Step::new(
+                "cmake",
+                &[
+                    "-B",
+                    BUILD_DIR,
+                    "-G",
+                    "Ninja",
+                    "-DCMAKE_INSTALL_PREFIX=/usr",
+                    "-DCMAKE_BUILD_TYPE=Release",
+                ],
+            )
+            .args(&configopts)],
+            BuildType::Meson => {
+                let mut setup = Step::new(
+                    "meson",
+                    &["setup", BUILD_DIR, "--prefix=/usr", "--buildtype=release"],
+                );
+                // meson refuses to set up the same build directory twice, a rerun reconfigures
+                // the one the last configure left behind
+                if self.srcdir.join(BUILD_DIR).is_dir() {
+                    setup = setup.args(&["--reconfigure".to_owned()]);
+                }
+                vec![setup.args(&configopts)]
+            }
+            BuildType::AutoTools => {
+                vec![Step::new("./configure", &["--prefix=/usr"]).args(&configopts)]
+            }
+        };
+        self.run_steps(Phase::Configure, steps)
+    }
+
+    pub fn build(&self) -> Result<()> {
+        let steps = match self.config.buildtype {
+            BuildType::Simple => self
+                .config
+                .buildsteps
+                .iter()
+                .map(|step| Step::shell(step))
+                .collect(),
+            BuildType::Cmake | BuildType::CmakeNinja => {
+                vec![Step::new("cmake", &["--build", BUILD_DIR])]
+            }
+            BuildType::Meson => vec![Step::new("meson", &["compile", "-C", BUILD_DIR])],
+            BuildType::AutoTools => vec![Step::new("make", &[])],
+        };
+        self.run_steps(Phase::Build, steps)
+    }
+
+    pub fn install(&self, pkgdir: &Path) -> Result<()> {
+        let steps = match self.config.buildtype {
+            BuildType::Simple => {
+                return self.install_steps(
+                    self.config.installsteps.as_deref().unwrap_or_default(),
+                    pkgdir,
+                );
+            }
+            BuildType::Cmake | BuildType::CmakeNinja => {
+                vec![Step::new("cmake", &["--install", BUILD_DIR]).env("DESTDIR", pkgdir)]
+            }
+            BuildType::Meson => {
+                vec![Step::new("meson", &["install", "-C", BUILD_DIR]).env("DESTDIR", pkgdir)]
+            }
+            BuildType::AutoTools => {
+                vec![Step::new("make", &["install"]).env("DESTDIR", pkgdir)]
+            }
+        };
+        self.run_steps(Phase::Install, steps)
+    }
+
+    // runs user supplied install commands with DESTDIR and pkgdir pointing at pkgdir
+    pub fn install_steps(&self, steps: &[String], pkgdir: &Path) -> Result<()> {
+        let steps = steps
+            .iter()
+            .map(|step| {
+                Step::shell(step)
+                    .env("DESTDIR", pkgdir)
+                    .env("pkgdir", pkgdir)
+            })
+            .collect();
+        self.run_steps(Phase::Install, steps)
+    }
+
+    fn run_steps(&self, phase: Phase, steps: Vec<Step>) -> Result<()> {
+        let total = steps.len();
+        for (i, step) in steps.into_iter().enumerate() {
+            self.progress
+                .message(&format!("[{phase} {}/{total}] {step}", i + 1));
+            let mut cmd = Command::new(&step.program);
+            cmd.args(&step.args).current_dir(&self.srcdir);
+            if let Some(env) = &self.config.env {
+                cmd.envs(env);
+            }
+            cmd.envs(step.env.iter().map(|(k, v)| (k, v)));
+            match cmd.status() {
+                Ok(status) => {
+                    if !status.success() {
+                        return Err(anyhow!(
+                            "{phase} step {} of {total} failed ({status}): {step}",
+                            i + 1
+                        ));
+                    }
+                }
+                Err(e) => {
+                    return Err(anyhow!(
+                        "{phase} step {} of {total} failed to start: {step}\n{e}",
+                        i + 1
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
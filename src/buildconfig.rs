@@ -1,278 +1,1516 @@
 //this defines build config as a struct along with a set of helper functions to deal with sources namely updating, downloading and verifying them
-use super::utils::{
-    calculate_sha56sum, download_with_pb, extract_with_sha, get_filename_from_url, git,
+use super::{
+    error::Error,
+    progress::{Progress, Quiet},
+    userconfig::{ExtractSettings, UserConfig, MIRROR_SCHEME},
+    utils::{
+        cache::Cache,
+        checksum::{hash_file, Algorithm},
+        copy_dir_all, download_with_pb, extract_archive, get_filename_from_url, git,
+        network::Network,
+        signature::{
+            minisign_key, normalize_fingerprint, verify_minisign, Keyring, PGP_SIGNATURE,
+            SSH_SIGNATURE,
+        },
+        spans::Spans,
+        ArchiveFilter, ArchiveFormat, SCHEMES,
+    },
 };
-use anyhow::{anyhow, Result};
-use git2::{build::CheckoutBuilder, Oid, Repository};
+use anyhow::{anyhow, Context, Result};
+use git2::{build::CheckoutBuilder, ObjectType, Oid, Repository};
+use glob::Pattern;
 use serde::Deserialize;
-use std::{collections::HashMap, fs::copy, path::PathBuf, str};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{copy, create_dir_all, read, read_to_string, remove_file},
+    path::{Component, Path, PathBuf},
+    str,
+    sync::Arc,
+};
 use url::Url;
 
+/// a faebuild.yaml recipe, the package it builds, where its sources come from and how it is
+/// built and packaged
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BuildConfig {
+    /// the package, or the main package followed by its split packages
     pub name: PkgName,
+    /// one line saying what the package is
+    pub description: Option<String>,
+    /// upstream's version, it is part of the package file name
     pub version: String,
+    /// bumped for every build of the same version
     pub rel: u32,
+    /// the architectures it builds on, any for packages that don't depend on one
     pub arch: Vec<String>,
-    pub url: Url,        //ensure this is a url
-    pub license: String, //TODO: VERIFY SPDX
+    /// the project's homepage
+    pub url: Url, //ensure this is a url
+    /// an spdx license expression
+    pub license: String, //checked against SPDX by faebuild lint
+    /// packages needed to run it
     pub depends: Option<Vec<String>>,
+    /// environment variables set for every configure, build and install step
     pub env: Option<HashMap<String, String>>,
+    /// directory below the build dir the steps run in
     pub subdir: Option<PathBuf>,
+    /// how it is configured, built and installed
     pub buildtype: BuildType,
+    /// extra arguments to the configure step, not for the simple buildtype
     pub configopts: Option<Vec<String>>,
+    /// packages needed to build it
     pub builddepends: Option<Vec<String>>,
+    /// shell commands run to build with the simple buildtype
+    #[serde(default)]
     pub buildsteps: Vec<String>,
+    /// shell commands run to install into $pkgdir with the simple buildtype
+    pub installsteps: Option<Vec<String>>,
+    /// what goes into each split package listed in name
+    pub packages: Option<Vec<SubPackage>>,
+    /// everything fetched before the build, in the order it is prepared
     pub sources: Vec<Sources>,
+    /// modes and owners set on paths of the staged package
+    pub permissions: Option<Vec<Permission>>,
+    /// fingerprints of the keys signatures are checked against, the keys are read from keys/pgp
+    pub validpgpkeys: Option<Vec<String>>,
+}
+
+/// a split package, the files it owns are either moved out of the main install by glob or
+/// installed by its own installsteps
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubPackage {
+    /// one of the names listed in name
+    pub name: String,
+    /// replaces the top level description, which only the main package falls back to
+    pub description: Option<String>,
+    /// replaces the top level depends, which only the main package falls back to
+    pub depends: Option<Vec<String>>,
+    /// globs of paths moved out of the main install into this package
+    pub files: Option<Vec<String>>,
+    /// shell commands installing into this package's own $pkgdir
+    pub installsteps: Option<Vec<String>>,
 }
 
+/// the name field of a recipe
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum PkgName {
+    /// a single package
     Name(String),
+    /// the main package first and then every split package
     MutliPackage(Vec<String>),
 }
 
+/// overrides applied to a path in the staged package and recorded in its manifest, so the
+/// installer can restore them when the build ran unprivileged
 #[derive(Debug, Deserialize)]
-pub enum Arch {
-    #[serde(rename = "any")]
-    Any,
-    MutliPackage(Vec<String>),
+#[serde(deny_unknown_fields)]
+pub struct Permission {
+    /// where in the package, / is the root of the package
+    pub path: PathBuf,
+    /// special bits added on top of the mode
+    pub permissions: Option<SpecialPerms>,
+    /// replaces the mode the install left, the file type bits are kept
+    pub mode: Option<Mode>,
+    /// a user in /etc/passwd
+    pub owner: Option<String>,
+    /// a group in /etc/group
+    pub group: Option<String>,
 }
 
+/// the permissions field of a [`Permission`], one bit or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SpecialPerms {
+    /// one bit
+    Single(SpecialBit),
+    /// every bit listed
+    Multiple(Vec<SpecialBit>),
+}
+
+/// a mode bit that has to be asked for by name
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub enum SpecialBit {
+    /// suid, runs as the owner of the file
+    #[serde(rename = "suid")]
+    Setuid,
+    /// sgid, runs as the group of the file or makes a directory's files inherit its group
+    #[serde(rename = "sgid")]
+    Setgid,
+    /// sticky, only the owner may remove files from a directory
+    #[serde(rename = "sticky")]
+    Sticky,
+}
+
+/// file mode bits in octal, 0755, 755 and 0o755 all mean the same whether quoted or not. it is
+/// kept as written, as a number yaml would already have turned 0o755 into 493, and checked by
+/// [`BuildConfig::validate`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Mode(pub String);
+
+impl Mode {
+    /// the mode bits, None when the text isn't an octal mode
+    pub fn bits(&self) -> Option<u32> {
+        let digits = self.0.strip_prefix("0o").unwrap_or(&self.0);
+        u32::from_str_radix(digits, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+    }
+}
+
+impl SpecialBit {
+    /// the bit as it is set in a mode
+    pub fn bits(self) -> u32 {
+        match self {
+            SpecialBit::Setuid => 0o4000,
+            SpecialBit::Setgid => 0o2000,
+            SpecialBit::Sticky => 0o1000,
+        }
+    }
+}
+
+impl Permission {
+    /// the mode this entry gives a file that currently has mode current
+    pub fn apply(&self, current: u32) -> u32 {
+        // validate has made sure the mode is valid
+        let mut mode = match self.mode.as_ref().and_then(Mode::bits) {
+            Some(mode) => mode,
+            None => current & 0o7777,
+        };
+        let special = match &self.permissions {
+            None => vec![],
+            Some(SpecialPerms::Single(bit)) => vec![*bit],
+            Some(SpecialPerms::Multiple(bits)) => bits.clone(),
+        };
+        for bit in special {
+            mode |= bit.bits();
+        }
+        mode
+    }
+
+    /// path relative to the root of the staged package
+    pub fn relative_path(&self) -> &Path {
+        self.path.strip_prefix("/").unwrap_or(&self.path)
+    }
+}
+
+/// a problem found while validating a recipe, path points at the offending node such as
+/// `sources[2].commit` so it can be mapped back to a line in the yaml
+#[derive(Debug)]
+pub struct Problem {
+    /// the offending node, `.` between keys and `[i]` for list items
+    pub path: String,
+    /// what is wrong with it
+    pub message: String,
+}
+
+impl Problem {
+    /// a problem with the node at path
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Problem {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// the problem as file:line:column: message, spans are those of the text file was read from
+    pub fn report(&self, file: &Path, spans: &Spans) -> String {
+        format!(
+            "{}:{}: {}",
+            file.display(),
+            spans.locate(&self.path),
+            self.message
+        )
+    }
+}
+
+// whether name is exactly one plain path component, package names end up as directory and
+// file names so anything else could write outside of where they are meant to go
+pub(crate) fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(part)) if part.to_str() == Some(name))
+        && components.next().is_none()
+}
+
+impl BuildConfig {
+    /// parses and validates a recipe, every validation problem is reported at once with its
+    /// file:line:column
+    pub fn load(path: &Path) -> Result<BuildConfig, Error> {
+        let text = read_to_string(path)
+            .context(format!("failed to read {}", path.display()))
+            .map_err(Error::Config)?;
+        let config = BuildConfig::parse(path, &text)?;
+
+        let problems = config.validate();
+        if problems.is_empty() {
+            return Ok(config);
+        }
+        let spans = Spans::parse(&text);
+        let report = problems
+            .iter()
+            .map(|problem| problem.report(path, &spans))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Err(Error::Config(anyhow!(
+            "{} is invalid ({} problems):\n{report}",
+            path.display(),
+            problems.len()
+        )))
+    }
+
+    /// deserializes a recipe without validating it, path is only used for error messages
+    pub fn parse(path: &Path, text: &str) -> Result<BuildConfig, Error> {
+        match serde_yaml::from_str(text) {
+            Ok(config) => Ok(config),
+            Err(e) => {
+                let message = e.to_string();
+                let message = match message.rsplit_once(" at line ") {
+                    Some((message, _)) => message.to_owned(),
+                    None => message,
+                };
+                Err(Error::Config(match e.location() {
+                    Some(location) => anyhow!(
+                        "{}:{}:{}: {message}",
+                        path.display(),
+                        location.line(),
+                        location.column()
+                    ),
+                    None => anyhow!("{}: {message}", path.display()),
+                }))
+            }
+        }
+    }
+
+    /// every problem serde can't catch on its own, such as fields that don't apply to the
+    /// buildtype or source type, sources without a checksum and names that aren't plain file
+    /// names. an empty list means the recipe is valid
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        let simple = matches!(self.buildtype, BuildType::Simple);
+        if simple && self.configopts.is_some() {
+            problems.push(Problem::new(
+                "configopts",
+                "configopts only apply to non-simple buildtypes",
+            ));
+        }
+        if !simple && !self.buildsteps.is_empty() {
+            problems.push(Problem::new(
+                "buildsteps",
+                "buildsteps only apply to the simple buildtype",
+            ));
+        }
+        if !simple && self.installsteps.is_some() {
+            problems.push(Problem::new(
+                "installsteps",
+                "installsteps only apply to the simple buildtype",
+            ));
+        }
+
+        for (i, source) in self.sources.iter().enumerate() {
+            let path = format!("sources[{i}]");
+            if let Some(SourceUrl::Multiple(urls)) = &source.url {
+                if urls.is_empty() {
+                    problems.push(Problem::new(format!("{path}.url"), "url list is empty"));
+                }
+            }
+            if source.size.is_some()
+                && (source.url.is_none() || matches!(source.r#type, SourceType::Git))
+            {
+                problems.push(Problem::new(
+                    format!("{path}.size"),
+                    "size only applies to archive, file and patch sources downloaded from a url",
+                ));
+            }
+            for (algorithm, sum) in source.checksums() {
+                if !algorithm.is_sum(sum) {
+                    problems.push(Problem::new(
+                        format!("{path}.{algorithm}"),
+                        format!(
+                            "{sum} is not a {algorithm}, expected {} lowercase hex characters",
+                            algorithm.hex_len()
+                        ),
+                    ));
+                }
+            }
+            if !matches!(source.r#type, SourceType::Git) {
+                for url in source.urls() {
+                    let scheme = url.scheme();
+                    if scheme != MIRROR_SCHEME && !SCHEMES.contains(&scheme) {
+                        problems.push(Problem::new(
+                            format!("{path}.url"),
+                            format!(
+                                "{url} uses {scheme}://, sources can only be downloaded over {}",
+                                SCHEMES.join(", ")
+                            ),
+                        ));
+                    }
+                }
+            }
+            if !matches!(source.r#type, SourceType::Archive)
+                && (source.format.is_some()
+                    || source.extract.is_some()
+                    || source.extract_to.is_some()
+                    || source.strip_components.is_some()
+                    || source.include.is_some()
+                    || source.exclude.is_some())
+            {
+                problems.push(Problem::new(
+                    &path,
+                    "format, extract, extract_to, strip_components, include and exclude only apply to archive sources",
+                ));
+            }
+            match source.r#type {
+                SourceType::Git => {
+                    if source.url.is_none() {
+                        problems.push(Problem::new(&path, "git sources need a url"));
+                    }
+                    if source.commit.is_none() {
+                        problems.push(Problem::new(&path, "git sources need a commit"));
+                    }
+                    if let (Some(SignedRef::Tag), None) = (source.signed, &source.tag) {
+                        problems.push(Problem::new(
+                            format!("{path}.signed"),
+                            "a signed tag needs the tag set",
+                        ));
+                    }
+                }
+                SourceType::Archive => {
+                    if source.url.is_none() {
+                        problems.push(Problem::new(&path, "archive sources need a url"));
+                    }
+                    if let Some(extract_to) = &source.extract_to {
+                        if !extract_to
+                            .components()
+                            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                        {
+                            problems.push(Problem::new(
+                                format!("{path}.extract_to"),
+                                "extract_to has to be a relative path inside the build dir",
+                            ));
+                        }
+                    }
+                    for (field, globs) in
+                        [("include", &source.include), ("exclude", &source.exclude)]
+                    {
+                        for (j, glob) in globs.iter().flatten().enumerate() {
+                            if let Err(e) = Pattern::new(glob) {
+                                problems.push(Problem::new(
+                                    format!("{path}.{field}[{j}]"),
+                                    format!("{glob} is not a valid glob, {e}"),
+                                ));
+                            }
+                        }
+                    }
+                    if source.extract == Some(false)
+                        && (source.strip_components.is_some()
+                            || source.include.is_some()
+                            || source.exclude.is_some())
+                    {
+                        problems.push(Problem::new(
+                            &path,
+                            "strip_components, include and exclude have no effect with extract: false",
+                        ));
+                    }
+                    if source.checksums().is_empty() {
+                        problems.push(Problem::new(
+                            &path,
+                            "archive sources need a sha256sum, sha512sum, b2sum or b3sum",
+                        ));
+                    }
+                }
+                SourceType::File | SourceType::Patch => {
+                    if source.url.is_none() && source.path.is_none() {
+                        problems.push(Problem::new(&path, "either url or path is required"));
+                    }
+                    if source.url.is_some() && source.checksums().is_empty() {
+                        problems.push(Problem::new(
+                            format!("{path}.url"),
+                            "sources downloaded from a url need a sha256sum, sha512sum, b2sum or b3sum",
+                        ));
+                    }
+                }
+            }
+        }
+
+        let validpgpkeys = self.validpgpkeys.as_deref().unwrap_or_default();
+        for (i, fingerprint) in validpgpkeys.iter().enumerate() {
+            let fingerprint = normalize_fingerprint(fingerprint);
+            if !matches!(fingerprint.len(), 40 | 64)
+                || !fingerprint.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                problems.push(Problem::new(
+                    format!("validpgpkeys[{i}]"),
+                    format!("{fingerprint} is not a full key fingerprint"),
+                ));
+            }
+        }
+        for (i, source) in self.sources.iter().enumerate() {
+            if source.signed.is_some() && !matches!(source.r#type, SourceType::Git) {
+                problems.push(Problem::new(
+                    format!("sources[{i}].signed"),
+                    "signed only applies to git sources, use signature for other sources",
+                ));
+            }
+            if let Some(minisign) = &source.minisign {
+                let path = format!("sources[{i}].minisign");
+                if let SourceType::Git = source.r#type {
+                    problems.push(Problem::new(
+                        &path,
+                        "minisign only applies to archive, file and patch sources",
+                    ));
+                }
+                if let Err(e) = minisign_key(&minisign.public_key) {
+                    problems.push(Problem::new(format!("{path}.public_key"), e.to_string()));
+                }
+            }
+            if source.signature.is_none() {
+                continue;
+            }
+            let path = format!("sources[{i}].signature");
+            if !matches!(source.r#type, SourceType::Archive | SourceType::File) {
+                problems.push(Problem::new(
+                    &path,
+                    "signatures are only checked on archive and file sources",
+                ));
+            } else if validpgpkeys.is_empty() {
+                problems.push(Problem::new(
+                    &path,
+                    "signatures need the keys they are made with listed in validpgpkeys",
+                ));
+            }
+        }
+
+        for (i, permission) in self.permissions.iter().flatten().enumerate() {
+            let relative = permission.relative_path();
+            if relative.as_os_str().is_empty()
+                || !relative
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+            {
+                problems.push(Problem::new(
+                    format!("permissions[{i}].path"),
+                    format!(
+                        "{} has to name a path inside the package, without . or .. in it",
+                        permission.path.display()
+                    ),
+                ));
+            }
+            if let Some(mode) = permission
+                .mode
+                .as_ref()
+                .filter(|mode| mode.bits().is_none())
+            {
+                problems.push(Problem::new(
+                    format!("permissions[{i}].mode"),
+                    format!("invalid mode {}, expected octal bits such as 0755", mode.0),
+                ));
+            }
+        }
+
+        // the version is part of the package file name
+        if !is_plain_name(&self.version) {
+            problems.push(Problem::new(
+                "version",
+                format!(
+                    "{:?} is not a valid version, it can't be empty, contain / or be . or ..",
+                    self.version
+                ),
+            ));
+        }
+
+        let names = self.name.names();
+        if names.is_empty() {
+            problems.push(Problem::new(
+                "name",
+                "name list is empty, it needs at least the main package",
+            ));
+        }
+        for (i, name) in names.iter().enumerate() {
+            if !is_plain_name(name) {
+                let path = match self.name {
+                    PkgName::Name(_) => "name".to_owned(),
+                    PkgName::MutliPackage(_) => format!("name[{i}]"),
+                };
+                problems.push(Problem::new(path, invalid_name(name)));
+            }
+        }
+        let packages = self.packages.as_deref().unwrap_or_default();
+        for (i, package) in packages.iter().enumerate() {
+            let path = format!("packages[{i}]");
+            if !is_plain_name(&package.name) {
+                problems.push(Problem::new(
+                    format!("{path}.name"),
+                    invalid_name(&package.name),
+                ));
+            } else if !names.contains(&package.name.as_str()) {
+                problems.push(Problem::new(
+                    format!("{path}.name"),
+                    format!("{} is not listed in name", package.name),
+                ));
+            } else if names.first() == Some(&package.name.as_str())
+                && (package.files.is_some() || package.installsteps.is_some())
+            {
+                problems.push(Problem::new(
+                    &path,
+                    format!("{} is the main package and gets every file not claimed by a split package, it can't set files or installsteps", package.name),
+                ));
+            }
+        }
+        for name in names.iter().skip(1) {
+            if self.subpackage(name).is_none() {
+                problems.push(Problem::new(
+                    "name",
+                    format!("split package {name} has no packages entry"),
+                ));
+            }
+        }
+        problems
+    }
+
+    /// the packages entry of split package name
+    pub fn subpackage(&self, name: &str) -> Option<&SubPackage> {
+        self.packages
+            .iter()
+            .flatten()
+            .find(|package| package.name == name)
+    }
+}
+
+fn invalid_name(name: &str) -> String {
+    format!("{name:?} is not a valid package name, it can't be empty, contain / or be . or ..")
+}
+
+impl PkgName {
+    /// every package name, the main package first
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            PkgName::Name(name) => vec![name.as_str()],
+            PkgName::MutliPackage(names) => names.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl fmt::Display for PkgName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PkgName::Name(name) => write!(f, "{name}"),
+            PkgName::MutliPackage(names) => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// how a package is configured, built and installed
 #[derive(Debug, Deserialize)]
 pub enum BuildType {
+    /// the recipe's own buildsteps and installsteps
     #[serde(rename = "simple")]
     Simple,
+    /// cmake generating ninja files
     #[serde(rename = "cmake-ninja")]
     CmakeNinja,
+    /// cmake generating makefiles
     #[serde(rename = "cmake")]
     Cmake,
+    /// meson setup, compile and install
     #[serde(rename = "meson")]
     Meson,
+    /// ./configure, make and make install
     #[serde(rename = "autotools")]
     AutoTools,
 }
 
-#[derive(Debug, Deserialize)]
+/// one entry of sources in a recipe, a git repository, an archive, a plain file or a patch
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sources {
+    /// what the source is
     pub r#type: SourceType,
+    /// a file next to the recipe, for sources that aren't downloaded
     pub path: Option<PathBuf>,
-    pub url: Option<Url>,
+    /// where the source is downloaded or cloned from
+    pub url: Option<SourceUrl>,
+    /// lowercase hex checksums, every one given has to match
     pub sha256sum: Option<String>,
+    /// see sha256sum
+    pub sha512sum: Option<String>,
+    /// see sha256sum
+    pub b2sum: Option<String>,
+    /// see sha256sum
+    pub b3sum: Option<String>,
+    /// git only, the full hash of the commit that is checked out
     pub commit: Option<String>,
+    /// git only, a tag that has to point at commit
     pub tag: Option<String>,
+    /// git only, whether submodules are cloned too, they are unless this is false
     pub recursive: Option<bool>,
+    /// a detached openpgp or ssh signature of the file
+    pub signature: Option<SignatureFile>,
+    /// git only, whether the tag or the commit has to carry a signature
+    pub signed: Option<SignedRef>,
+    /// a minisign signature of the file
+    pub minisign: Option<Minisign>,
+    /// bytes, downloads that turn out to be any other size are stopped early
+    pub size: Option<u64>,
+    /// archives only, the format is sniffed from the file when this isn't set
+    pub format: Option<ArchiveFormat>,
+    /// archives only, false copies the archive into the build dir as is
+    pub extract: Option<bool>,
+    /// directory below the build dir the archive is unpacked or copied into
+    pub extract_to: Option<PathBuf>,
+    /// leading path components dropped from every entry, like tar --strip-components
+    pub strip_components: Option<usize>,
+    /// globs matched against entry paths after stripping, everything is unpacked when include
+    /// is empty and exclude wins over include
+    pub include: Option<Vec<String>>,
+    /// see include
+    pub exclude: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
+/// a minisign signature and the public key it has to be made with
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Minisign {
+    /// the base64 public key, as on the second line of a minisign .pub file
+    pub public_key: String,
+    /// the .minisig file
+    pub signature: SignatureFile,
+}
+
+/// a detached signature, downloaded from a url or taken from a path next to the recipe
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SignatureFile {
+    /// downloaded fresh on every fetch
+    Url(Url),
+    /// relative to the recipe directory
+    Path(PathBuf),
+}
+
+/// the git object whose signature a git source has to carry
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum SignedRef {
+    /// the annotated tag named by tag
+    #[serde(rename = "tag")]
+    Tag,
+    /// the commit itself
+    #[serde(rename = "commit")]
+    Commit,
+}
+
+/// one url or an ordered list of urls serving the same file, later ones are fallbacks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SourceUrl {
+    /// a single url
+    Single(Url),
+    /// the urls in the order they are tried
+    Multiple(Vec<Url>),
+}
+
+/// what a source is and so what prepare does with it
+#[derive(Debug, Clone, Deserialize)]
 pub enum SourceType {
+    /// a repository cloned and checked out at commit
     #[serde(rename = "git")]
     Git,
+    /// unpacked into the build dir
     #[serde(rename = "archive")]
     Archive,
+    /// copied into the build dir as is
     #[serde(rename = "file")]
     File,
+    /// applied with patch -p1 once every other source is in place
     #[serde(rename = "patch")]
     Patch,
 }
 
-impl Sources {
-    pub async fn fetch(self, src: &PathBuf, workdir: &PathBuf) -> Result<PathBuf> {
-        match self.r#type {
-            SourceType::Archive => match self.url {
-                None => {
-                    return Err(anyhow!(
-                        "Source type was set to archive but no url was provided"
-                    ));
+/// settings shared by every source fetch
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    /// never touch the network, only verify what is already in src
+    pub offline: bool,
+    /// shared cache consulted before anything is downloaded
+    pub cache: Option<Cache>,
+    /// mirrors from the user config, tried before the urls they cover
+    pub user_config: UserConfig,
+    /// the proxy, certificates and credentials every download and git fetch goes through
+    pub network: Network,
+    /// how many sources are fetched at once
+    pub jobs: usize,
+    /// every download, git fetch and step of the build is reported to this
+    pub progress: Arc<dyn Progress>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            offline: false,
+            cache: None,
+            user_config: UserConfig::default(),
+            network: Network::default(),
+            jobs: 0,
+            progress: Arc::new(Quiet),
+        }
+    }
+}
+
+impl fmt::Display for SourceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceType::Git => write!(f, "git"),
+            SourceType::Archive => write!(f, "archive"),
+            SourceType::File => write!(f, "file"),
+            SourceType::Patch => write!(f, "patch"),
+        }
+    }
+}
+
+impl SignatureFile {
+    /// where the signature is kept under src
+    pub fn local_path(&self, src: &Path) -> Result<PathBuf> {
+        let name = match self {
+            SignatureFile::Url(url) => get_filename_from_url(url),
+            SignatureFile::Path(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        };
+        match name {
+            Some(name) => Ok(src.join(name)),
+            None => Err(anyhow!("{self} does not name a file")),
+        }
+    }
+
+    /// signatures are small and carry no checksum of their own, so they are fetched fresh
+    /// unless offline
+    pub async fn fetch(
+        &self,
+        src: &Path,
+        recipedir: &Path,
+        options: &FetchOptions,
+    ) -> Result<PathBuf> {
+        let out = self.local_path(src)?;
+        match self {
+            SignatureFile::Url(_) if options.offline => {
+                if !out.exists() {
+                    return Err(anyhow!("{} has not been fetched", out.display()));
                 }
-                Some(url) => match self.sha256sum {
-                    None => {
-                        return Err(anyhow!(
-                            "Source type was set to archive but no sha256sum was provided"
-                        ));
-                    }
-                    Some(sha256sum) => {
-                        let selfpath = self.path;
-
-                        let out: PathBuf = if let Some(selfpath) = selfpath {
-                            selfpath
-                        } else {
-                            match get_filename_from_url(&url) {
-                                Some(path) => PathBuf::from(path),
-                                None => PathBuf::from("out.tar.gz"),
-                            }
-                        };
+            }
+            SignatureFile::Url(url) => {
+                if out.exists() {
+                    remove_file(&out)?;
+                }
+                download_with_pb(
+                    url.clone(),
+                    &out,
+                    None,
+                    &options.network,
+                    &options.user_config.download,
+                    &*options.progress,
+                )
+                .await?;
+            }
+            SignatureFile::Path(path) => {
+                copy(recipedir.join(path), &out)
+                    .context(format!("failed to copy {}", path.display()))?;
+            }
+        }
+        Ok(out)
+    }
+}
 
-                        let src_out = src.join(out);
-                        if src_out.exists() {
-                            let cached_sum = calculate_sha56sum(&src_out).await?;
+impl fmt::Display for SignatureFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureFile::Url(url) => write!(f, "{url}"),
+            SignatureFile::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
 
-                            if cached_sum == sha256sum {
-                                extract_with_sha(sha256sum, &src_out, &workdir).await
-                            } else {
-                                download_with_pb(url, &src_out).await?;
-                                extract_with_sha(sha256sum, &src_out, &workdir).await
-                            }
-                        } else {
-                            download_with_pb(url, &src_out).await?;
-                            extract_with_sha(sha256sum, &src_out, &workdir).await
-                        }
-                    }
+impl SourceUrl {
+    /// every url in the order they are tried
+    pub fn urls(&self) -> Vec<&Url> {
+        match self {
+            SourceUrl::Single(url) => vec![url],
+            SourceUrl::Multiple(urls) => urls.iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.url(), &self.path) {
+            (Some(url), _) => write!(f, "{} {url}", self.r#type),
+            (None, Some(path)) => write!(f, "{} {}", self.r#type, path.display()),
+            (None, None) => write!(f, "{}", self.r#type),
+        }
+    }
+}
+
+impl Sources {
+    /// the first url, it names the source and what it is stored under
+    pub fn url(&self) -> Option<&Url> {
+        self.urls().into_iter().next()
+    }
+
+    /// every url in the order they are tried, empty for sources without one
+    pub fn urls(&self) -> Vec<&Url> {
+        self.url.as_ref().map(SourceUrl::urls).unwrap_or_default()
+    }
+
+    /// the sum the source is pinned with for algorithm
+    pub fn checksum(&self, algorithm: Algorithm) -> Option<&str> {
+        match algorithm {
+            Algorithm::Sha256 => self.sha256sum.as_deref(),
+            Algorithm::Sha512 => self.sha512sum.as_deref(),
+            Algorithm::Blake2b => self.b2sum.as_deref(),
+            Algorithm::Blake3 => self.b3sum.as_deref(),
+        }
+    }
+
+    /// every checksum the source is pinned with, all of them have to match
+    pub fn checksums(&self) -> Vec<(Algorithm, &str)> {
+        Algorithm::ALL
+            .into_iter()
+            .filter_map(|algorithm| Some((algorithm, self.checksum(algorithm)?)))
+            .collect()
+    }
+
+    /// what the source is stored under in the cache, sha256sums are used bare as they were
+    /// the only kind of sum before the others existed
+    pub fn cache_key(&self) -> Option<String> {
+        let (algorithm, sum) = *self.checksums().first()?;
+        match algorithm {
+            Algorithm::Sha256 => Some(sum.to_owned()),
+            algorithm => Some(format!("{}-{sum}", algorithm.field())),
+        }
+    }
+
+    /// where the source comes from, its first url or the path in the recipe
+    pub fn origin(&self) -> String {
+        match (self.url(), &self.path) {
+            (Some(url), _) => url.to_string(),
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) => self.r#type.to_string(),
+        }
+    }
+
+    /// checks path against every checksum of the source, reading it only once. origin is
+    /// where path came from, for the error when it doesn't match
+    pub fn verify(&self, path: &Path, origin: &str) -> Result<(), Error> {
+        let checksums = self.checksums();
+        if checksums.is_empty() {
+            return Ok(());
+        }
+        let algorithms: Vec<Algorithm> =
+            checksums.iter().map(|(algorithm, _)| *algorithm).collect();
+        let actual = hash_file(path, &algorithms)?;
+        for ((algorithm, expected), actual) in checksums.into_iter().zip(actual) {
+            if actual != expected {
+                return Err(Error::Checksum {
+                    file: path.to_owned(),
+                    origin: origin.to_owned(),
+                    algorithm,
+                    expected: expected.to_owned(),
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// name a downloaded source is stored under in src/, archives may pick it with path
+    pub fn download_name(&self) -> Option<PathBuf> {
+        let url = self.url()?;
+        if let (SourceType::Archive, Some(path)) = (&self.r#type, &self.path) {
+            return Some(path.clone());
+        }
+        match get_filename_from_url(url) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(PathBuf::from("out.tar.gz")),
+        }
+    }
+
+    /// where the fetched source is kept under src
+    pub fn local_path(&self, src: &Path) -> Result<PathBuf> {
+        match self.r#type {
+            SourceType::Git => {
+                let url = self
+                    .url()
+                    .ok_or(anyhow!("Url is required for git source"))?;
+                let basename = url
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .filter(|segment| !segment.is_empty())
+                    .ok_or(anyhow!("failed to get a repository name from {url}"))?;
+                Ok(src.join(basename.trim_end_matches(".git")))
+            }
+            _ => match (self.download_name(), &self.path) {
+                (Some(name), _) => Ok(src.join(name)),
+                (None, Some(path)) => match path.file_name() {
+                    Some(name) => Ok(src.join(name)),
+                    None => Err(anyhow!("{} is not a file", path.display())),
                 },
+                (None, None) => Err(anyhow!("either url or path is required")),
             },
-            SourceType::Git => {
-                if self.commit.is_none() {
-                    return Err(anyhow!("Commit is required for git sources"));
-                }
+        }
+    }
 
-                if let Some(url) = self.url {
-                    let url_path = url.path_segments().unwrap();
-                    let basename = url_path.last().unwrap().replace(".git", "");
-                    let out = src.join(basename);
-                    let mut recursive = true;
-                    let repo: Repository;
-                    if let Some(rec) = self.recursive {
-                        recursive = rec
-                    }
-                    if out.exists() {
-                        repo = Repository::open(&out)?;
-                        git::fetch(&repo)?;
-                    } else {
-                        if recursive {
-                            repo = match Repository::clone_recurse(&url.to_string(), &out) {
-                                Ok(repo) => repo,
-                                Err(e) => {
-                                    return Err(anyhow!("Failed to clone repo: {url}\n{e}"));
-                                }
-                            }
-                        } else {
-                            repo = match Repository::clone(&url.to_string(), &out) {
-                                Ok(repo) => repo,
-                                Err(e) => {
-                                    return Err(anyhow!("Failed to clone repo: {url}\n{e}"));
-                                }
+    /// fetches the source into src, downloads are verified against their checksums and local
+    /// files are taken relative to recipedir
+    pub async fn fetch(
+        &self,
+        src: &Path,
+        recipedir: &Path,
+        options: &FetchOptions,
+    ) -> Result<PathBuf, Error> {
+        self.fetch_to(src, recipedir, options)
+            .await
+            .map_err(|cause| Error::Fetch {
+                what: self.to_string(),
+                cause,
+            })
+    }
+
+    async fn fetch_to(
+        &self,
+        src: &Path,
+        recipedir: &Path,
+        options: &FetchOptions,
+    ) -> Result<PathBuf> {
+        let out = self.local_path(src)?;
+        if let SourceType::Git = self.r#type {
+            // git2 blocks, keep it off the threads driving the other downloads
+            let (source, options, repo) = (self.clone(), options.clone(), out.clone());
+            tokio::task::spawn_blocking(move || source.fetch_git(&repo, &options)).await??;
+            return Ok(out);
+        }
+
+        self.fetch_file(&out, recipedir, options).await?;
+        if let Some(signature) = &self.signature {
+            signature.fetch(src, recipedir, options).await?;
+        }
+        if let Some(minisign) = &self.minisign {
+            minisign.signature.fetch(src, recipedir, options).await?;
+        }
+        Ok(out)
+    }
+
+    async fn fetch_file(&self, out: &Path, recipedir: &Path, options: &FetchOptions) -> Result<()> {
+        match self.url() {
+            Some(url) => {
+                let key = self
+                    .cache_key()
+                    .ok_or(anyhow!("{url} was provided without a checksum"))?;
+                let mut mismatch = None;
+                let mut corrupted = false;
+                if out.exists() {
+                    match self.verify(out, &self.origin()) {
+                        Ok(()) => {
+                            if let Some(cache) = &options.cache {
+                                cache.store(&key, out)?;
                             }
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            remove_file(out)?;
+                            mismatch = Some(e);
                         }
                     }
-                    if let Some(commit_str) = self.commit {
-                        if let Some(tag) = self.tag {
-                            let oid = repo.refname_to_id(&tag)?;
-                            let commit = repo.find_commit(oid)?;
-                            if oid.to_string() != commit_str {
-                                return Err(anyhow!(
-                                    "expected tag: {tag} to resolve to {commit_str} was {}",
-                                    commit.id()
-                                ));
-                            }
-                            let mut checkout_options = CheckoutBuilder::new();
-                            repo.checkout_tree(commit.as_object(), Some(&mut checkout_options))?;
-                            repo.set_head_detached(oid)?;
-                        } else {
-                            let oid = Oid::from_str(&commit_str).unwrap();
-                            let commit = repo.find_commit(oid)?;
-                            let mut checkout_options = CheckoutBuilder::new();
-                            repo.checkout_tree(commit.as_object(), Some(&mut checkout_options))?;
-                            repo.set_head_detached(oid)?;
+                }
+                if let Some(cache) = &options.cache {
+                    if cache.restore(&key, out)? {
+                        if self.verify(out, &self.origin()).is_ok() {
+                            return Ok(());
                         }
-                    } else {
-                        unreachable!();
+                        // a corrupted cache entry is downloaded again and replaced once the
+                        // download verifies
+                        remove_file(out)?;
+                        corrupted = true;
                     }
+                }
+                if options.offline {
+                    return Err(match mismatch {
+                        Some(e) => anyhow!("the copy in src is stale, {e}"),
+                        None => anyhow!("{} has not been fetched", out.display()),
+                    });
+                }
+                self.download(out, options, true).await?;
+                match &options.cache {
+                    Some(cache) if corrupted => cache.replace(&key, out)?,
+                    Some(cache) => cache.store(&key, out)?,
+                    None => {}
+                }
+            }
+            None => {
+                let path = self
+                    .path
+                    .as_ref()
+                    .ok_or(anyhow!("either url or path is required"))?;
+                copy(recipedir.join(path), out)
+                    .context(format!("failed to copy {}", path.display()))?;
+                self.verify(out, &path.display().to_string())?;
+            }
+        }
+        Ok(())
+    }
 
-                    Ok(out)
-                } else {
-                    return Err(anyhow!("Url is required for git source"));
+    /// downloads the source to out from the first of its urls or their mirrors that works, a
+    /// download that fails verify counts as a failed one
+    pub async fn download(&self, out: &Path, options: &FetchOptions, verify: bool) -> Result<Url> {
+        let mut errors = vec![];
+        for url in options.user_config.candidates(self.urls()) {
+            if out.exists() {
+                remove_file(out)?;
+            }
+            let download = download_with_pb(
+                url.clone(),
+                out,
+                self.size,
+                &options.network,
+                &options.user_config.download,
+                &*options.progress,
+            );
+            if let Err(e) = download.await {
+                errors.push((url, e));
+                continue;
+            }
+            if verify {
+                if let Err(e) = self.verify(out, url.as_str()) {
+                    remove_file(out)?;
+                    errors.push((url, e.into()));
+                    continue;
                 }
             }
+            options
+                .progress
+                .message(&format!("{} served by {url}", out.display()));
+            return Ok(url);
+        }
+        Err(fallback_error(self, errors))
+    }
 
-            SourceType::File => {
-                if self.url.is_some() {
-                    if self.sha256sum.is_none() {
-                        return Err(anyhow!("Source type was set to File and there was a url but no sha256sum was provided"));
+    fn fetch_git(&self, out: &Path, options: &FetchOptions) -> Result<()> {
+        let url = self
+            .url()
+            .ok_or(anyhow!("Url is required for git source"))?;
+        let commit_str = self
+            .commit
+            .as_ref()
+            .ok_or(anyhow!("Commit is required for git sources"))?;
+
+        let oid = Oid::from_str(commit_str)?;
+
+        let candidates = options.user_config.candidates(self.urls());
+        let mut errors = vec![];
+        let mut fetched = None;
+        for candidate in &candidates {
+            match self.fetch_git_from(candidate, oid, out, options) {
+                Ok(repo) => {
+                    if candidates.len() > 1 {
+                        options
+                            .progress
+                            .message(&format!("{} served by {candidate}", out.display()));
                     }
-                    if let Some(url) = self.url {
-                        let out = match get_filename_from_url(&url) {
-                            Some(path) => PathBuf::from(path),
-                            None => PathBuf::from("out.tar.gz"),
-                        };
+                    fetched = Some(repo);
+                    break;
+                }
+                Err(e) => errors.push((candidate.clone(), e)),
+            }
+        }
+        let repo = fetched.ok_or_else(|| fallback_error(self, errors))?;
 
-                        let outfile = src.join(&out);
+        let commit = repo
+            .find_commit(oid)
+            .context(format!("commit {commit_str} does not exist in {url}"))?;
+        if let Some(tag) = &self.tag {
+            let refname = if tag.starts_with("refs/") {
+                tag.to_owned()
+            } else {
+                format!("refs/tags/{tag}")
+            };
+            let tagged = repo.revparse_single(&refname)?.peel_to_commit()?;
+            if tagged.id() != commit.id() {
+                return Err(anyhow!(
+                    "expected tag: {tag} to resolve to {commit_str} was {}",
+                    tagged.id()
+                ));
+            }
+        }
+        let mut checkout_options = CheckoutBuilder::new();
+        checkout_options.force();
+        repo.checkout_tree(commit.as_object(), Some(&mut checkout_options))?;
+        repo.set_head_detached(commit.id())?;
+        Ok(())
+    }
 
-                        download_with_pb(url, &outfile).await?;
+    // brings the clone at out up to date from url and makes sure it has commit
+    fn fetch_git_from(
+        &self,
+        url: &Url,
+        oid: Oid,
+        out: &Path,
+        options: &FetchOptions,
+    ) -> Result<Repository> {
+        // with a cache the recipe copy is cloned from a shared bare mirror instead of upstream
+        let mut upstream = url.clone();
+        if let Some(cache) = &options.cache {
+            let current = cache
+                .mirror(url, false, &options.network, &*options.progress)
+                .is_ok_and(|mirror| mirror.find_commit(oid).is_ok());
+            if !current && !options.offline {
+                cache.mirror(url, true, &options.network, &*options.progress)?;
+            }
+            let mirror = cache.mirror_path(url);
+            if mirror.exists() {
+                upstream = Url::from_file_path(&mirror)
+                    .map_err(|_| anyhow!("{} is not an absolute path", mirror.display()))?;
+            }
+        }
 
-                        let shasumactual = calculate_sha56sum(&outfile).await?;
+        let repo = if out.exists() {
+            let repo = Repository::open(out)?;
+            if repo.find_commit(oid).is_err() && !options.offline {
+                // the clone may have come from another of the source's urls
+                repo.remote_set_url("origin", upstream.as_str())?;
+                git::fetch(&repo, &options.network, &*options.progress)?;
+            }
+            repo
+        } else if options.offline && upstream.scheme() != "file" {
+            return Err(anyhow!("{url} has not been cloned into {}", out.display()));
+        } else {
+            let recursive = self.recursive.unwrap_or(true);
+            match git::clone(upstream.as_str(), out, recursive, &options.network) {
+                Ok(repo) => repo,
+                Err(e) => {
+                    return Err(anyhow!("Failed to clone repo: {url}\n{e}"));
+                }
+            }
+        };
+        if repo.find_commit(oid).is_err() {
+            return Err(anyhow!("commit {oid} does not exist in {url}"));
+        }
+        Ok(repo)
+    }
 
-                        if let Some(sha256sum) = self.sha256sum {
-                            if sha256sum != shasumactual {
-                                return Err(anyhow!(
-                                    "expected sha for {} was {} expected {}",
-                                    out.display(),
-                                    shasumactual,
-                                    sha256sum
-                                ));
-                            }
-                        } else {
-                            unreachable!()
-                        }
+    // checks the detached pgp and minisign signatures of the fetched source, if it has any
+    fn check_signature(
+        &self,
+        local: &Path,
+        src: &Path,
+        keyring: &Keyring,
+        progress: &dyn Progress,
+    ) -> Result<()> {
+        if let Some(signature) = &self.signature {
+            let path = signature.local_path(src)?;
+            let signer = keyring.verify(local, &read(&path)?)?;
+            progress.message(&format!("{} signed by {signer}", local.display()));
+        }
+        if let Some(minisign) = &self.minisign {
+            let path = minisign.signature.local_path(src)?;
+            let comment = verify_minisign(local, &minisign.public_key, &path)?;
+            progress.message(&format!(
+                "{} signed by minisign key {} ({comment})",
+                local.display(),
+                minisign.public_key
+            ));
+        }
+        Ok(())
+    }
 
-                        Ok(out)
-                    } else {
-                        unreachable!()
-                    }
+    // checks the signature git stored on the tag or commit a git source pins, ssh signatures
+    // are checked against keys/ssh/allowed_signers and pgp ones against validpgpkeys
+    fn check_git_signature(
+        &self,
+        local: &Path,
+        keyring: &Keyring,
+        progress: &dyn Progress,
+    ) -> Result<()> {
+        let (Some(signed), Some(commit)) = (self.signed, &self.commit) else {
+            return Ok(());
+        };
+        let repo = Repository::open(local)?;
+        let (what, signature, data) = match signed {
+            SignedRef::Commit => {
+                let what = format!("commit {commit}");
+                let (signature, data) = repo
+                    .extract_signature(&Oid::from_str(commit)?, None)
+                    .map_err(|_| anyhow!("{what} is not signed"))?;
+                (what, signature.to_vec(), data.to_vec())
+            }
+            SignedRef::Tag => {
+                let tag = self.tag.as_deref().unwrap_or_default();
+                let what = format!("tag {tag}");
+                let refname = if tag.starts_with("refs/") {
+                    tag.to_owned()
                 } else {
-                    if let Some(path) = self.path {
-                        let srcpath = src.join(&path);
-                        copy(path, &srcpath)?;
-                        Ok(srcpath)
-                    } else {
-                        Err(anyhow!("either url or path is required"))
-                    }
+                    format!("refs/tags/{tag}")
+                };
+                let oid = repo
+                    .refname_to_id(&refname)
+                    .context(format!("{what} does not exist in {}", local.display()))?;
+                let odb = repo.odb()?;
+                let object = odb.read(oid)?;
+                if object.kind() != ObjectType::Tag {
+                    return Err(anyhow!(
+                        "{what} is a lightweight tag, only annotated tags can be signed"
+                    ));
                 }
+                // git appends the signature to the tag message, what it signed is everything
+                // before it
+                let raw = object.data();
+                let start = [PGP_SIGNATURE, SSH_SIGNATURE]
+                    .iter()
+                    .filter_map(|marker| {
+                        raw.windows(marker.len())
+                            .position(|window| window == marker.as_bytes())
+                    })
+                    .min()
+                    .ok_or_else(|| anyhow!("{what} is not signed"))?;
+                (what, raw[start..].to_vec(), raw[..start].to_vec())
             }
-            SourceType::Patch => {
-                if self.url.is_some() {
-                    if self.sha256sum.is_none() {
-                        return Err(anyhow!("Source type was set to File and there was a url but no sha256sum was provided"));
-                    }
-                    if let Some(url) = self.url {
-                        let out = match get_filename_from_url(&url) {
-                            Some(path) => PathBuf::from(path),
-                            None => PathBuf::from("out.tar.gz"),
-                        };
+        };
+        let signer = keyring.verify_git(&what, &data, &signature)?;
+        progress.message(&format!("{what} signed by {signer}"));
+        Ok(())
+    }
 
-                        let outfile = src.join(&out);
+    /// puts a fetched source into workdir, patches stay in src and their path is returned so
+    /// they can be applied once everything is in place
+    pub async fn extract(
+        &self,
+        src: &Path,
+        workdir: &Path,
+        keyring: &Keyring,
+        settings: &ExtractSettings,
+        progress: &dyn Progress,
+    ) -> Result<PathBuf, Error> {
+        let local = self.local_path(src)?;
+        self.check_signature(&local, src, keyring, progress)
+            .map_err(|cause| Error::Signature {
+                file: local.clone(),
+                cause,
+            })?;
+        match self.r#type {
+            SourceType::Archive => {
+                if self.checksums().is_empty() {
+                    return Err(Error::Config(anyhow!(
+                        "Source type was set to archive but no checksum was provided"
+                    )));
+                }
+                self.verify(&local, &self.origin())?;
+                let dest = match &self.extract_to {
+                    Some(extract_to) => workdir.join(extract_to),
+                    None => workdir.to_owned(),
+                };
+                if self.extract == Some(false) {
+                    create_dir_all(&dest)?;
+                    let out = dest.join(local.file_name().unwrap_or_default());
+                    copy(&local, &out)?;
+                    return Ok(out);
+                }
+                let filter = ArchiveFilter::new(
+                    self.strip_components.unwrap_or_default(),
+                    self.include.as_deref().unwrap_or_default(),
+                    self.exclude.as_deref().unwrap_or_default(),
+                )
+                .map_err(Error::Config)?;
+                extract_archive(&local, &dest, self.format, &filter, settings).await
+            }
+            SourceType::Git => {
+                self.check_git_signature(&local, keyring, progress)
+                    .map_err(|cause| Error::Signature {
+                        file: local.clone(),
+                        cause,
+                    })?;
+                let out = workdir.join(local.file_name().unwrap_or_default());
+                copy_dir_all(&local, &out)?;
+                Ok(out)
+            }
+            SourceType::File => {
+                let out = workdir.join(local.file_name().unwrap_or_default());
+                copy(&local, &out)?;
+                Ok(out)
+            }
+            SourceType::Patch => Ok(local),
+        }
+    }
+}
 
-                        download_with_pb(url, &outfile).await?;
+// the error for a source none of whose urls worked, a single url keeps its own error
+fn fallback_error(source: &Sources, mut errors: Vec<(Url, anyhow::Error)>) -> anyhow::Error {
+    if errors.is_empty() {
+        return anyhow!("{source} has no url to fetch from, mirror:// urls need a matching mirror in the user config");
+    }
+    if errors.len() == 1 {
+        return errors.remove(0).1;
+    }
+    let report = errors
+        .iter()
+        .map(|(url, e)| format!("  {url}: {e}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    anyhow!(
+        "failed to fetch {source} from any of its {} urls:\n{report}",
+        errors.len()
+    )
+}
 
-                        let shasumactual = calculate_sha56sum(&outfile).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                        if let Some(sha256sum) = self.sha256sum {
-                            if sha256sum != shasumactual {
-                                return Err(anyhow!(
-                                    "expected sha for {} was {} expected {}",
-                                    out.display(),
-                                    shasumactual,
-                                    sha256sum
-                                ));
-                            }
-                        } else {
-                            unreachable!()
-                        }
+    const RECIPE: &str = "\
+version: 1.0.0
+rel: 1
+arch: [any]
+url: https://example.org
+license: MIT
+buildtype: simple
+";
 
-                        Ok(out)
-                    } else {
-                        unreachable!()
-                    }
-                } else {
-                    if let Some(path) = self.path {
-                        let srcpath = src.join(&path);
-                        copy(path, &srcpath)?;
-                        Ok(srcpath)
-                    } else {
-                        Err(anyhow!("either url or path is required"))
-                    }
-                }
-            }
+    // every problem of the recipe made of name and the lines in rest, as line:col: message
+    fn problems(name: &str, rest: &str) -> Vec<String> {
+        report(&format!("name: {name}\n{RECIPE}sources: []\n{rest}"))
+    }
+
+    // every problem of the recipe with sources, starting on a new line, as its source list
+    fn source_problems(sources: &str) -> Vec<String> {
+        report(&format!("name: foo\n{RECIPE}sources:{sources}"))
+    }
+
+    fn report(text: &str) -> Vec<String> {
+        let config = BuildConfig::parse(Path::new("faebuild.yaml"), text).unwrap();
+        let spans = Spans::parse(text);
+        config
+            .validate()
+            .iter()
+            .map(|problem| {
+                let report = problem.report(Path::new("faebuild.yaml"), &spans);
+                report["faebuild.yaml:".len()..].to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_plain_package_names() {
+        assert!(problems("foo", "").is_empty());
+        let split = "packages:\n  - name: foo-doc\n    files: [usr/share/doc]\n";
+        assert!(problems("[foo, foo-doc]", split).is_empty());
+    }
+
+    #[test]
+    fn reads_every_spelling_of_a_mode() {
+        for mode in ["0755", "755", "0o755", "'0o755'", "\"0755\""] {
+            let parsed: Mode = serde_yaml::from_str(mode).unwrap();
+            assert_eq!(parsed.bits(), Some(0o755), "{mode}");
+        }
+        let parsed: Mode = serde_yaml::from_str("4755").unwrap();
+        assert_eq!(parsed.bits(), Some(0o4755));
+    }
+
+    #[test]
+    fn refuses_invalid_modes_at_the_mode() {
+        for mode in ["0o999", "17777", "rwxr-xr-x", "-1"] {
+            let rest = format!("permissions:\n  - path: /usr/bin/foo\n    mode: {mode}\n");
+            assert_eq!(
+                problems("foo", &rest),
+                [format!(
+                    "11:5: invalid mode {mode}, expected octal bits such as 0755"
+                )]
+            );
         }
     }
+
+    #[test]
+    fn refuses_versions_that_are_paths() {
+        let text = RECIPE.replace("version: 1.0.0", "version: ../../1.0.0");
+        let text = format!("name: foo\n{text}sources: []\n");
+        let config = BuildConfig::parse(Path::new("faebuild.yaml"), &text).unwrap();
+        let problems = config.validate();
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        assert_eq!(problems[0].path, "version");
+    }
+
+    #[test]
+    fn refuses_package_names_that_are_paths() {
+        for name in ["../../victim", "/victim", "a/b", "..", ".", "''"] {
+            let problems = problems(name, "");
+            assert_eq!(problems.len(), 1, "{name}: {problems:?}");
+            assert!(problems[0].starts_with("1:1: "), "{name}: {problems:?}");
+            assert!(problems[0].contains("is not a valid package name"));
+        }
+        let split = "packages:\n  - name: ../../victim\n    files: [usr]\n";
+        let problems = problems("[main, ../../victim]", split);
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("1:14: \"../../victim\" is not a valid package name"));
+        assert!(problems[1].starts_with("10:5: \"../../victim\" is not a valid package name"));
+    }
+
+    #[test]
+    fn refuses_git_sources_without_a_commit() {
+        let sources = "
+  - type: file
+    path: a.patch
+  - type: patch
+    path: b.patch
+  - type: git
+    url: https://example.org/foo.git
+";
+        assert_eq!(
+            source_problems(sources),
+            ["13:5: git sources need a commit"]
+        );
+    }
+
+    #[test]
+    fn refuses_sources_without_a_checksum() {
+        let sources = "
+  - type: archive
+    url: https://example.org/foo.tar.gz
+  - type: file
+    url: https://example.org/foo.service
+";
+        assert_eq!(
+            source_problems(sources),
+            [
+                "9:5: archive sources need a sha256sum, sha512sum, b2sum or b3sum",
+                "12:5: sources downloaded from a url need a sha256sum, sha512sum, b2sum or b3sum",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_configopts_on_simple_buildtypes() {
+        assert_eq!(
+            problems("foo", "configopts: [--enable-foo]\n"),
+            ["9:1: configopts only apply to non-simple buildtypes"]
+        );
+        let text = format!("name: foo\n{RECIPE}sources: []\nconfigopts: [--enable-foo]\n")
+            .replace("buildtype: simple", "buildtype: meson");
+        assert!(report(&text).is_empty());
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let sources = "
+  - type: git
+    url: https://example.org/foo.git
+  - type: archive
+    url: https://example.org/foo.tar.gz
+    sha256sum: abc
+configopts: [--enable-foo]
+";
+        assert_eq!(
+            source_problems(sources),
+            [
+                "14:1: configopts only apply to non-simple buildtypes",
+                "9:5: git sources need a commit",
+                "13:5: abc is not a sha256sum, expected 64 lowercase hex characters",
+            ]
+        );
+    }
 }
@@ -11,14 +11,62 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub struct Cli {
     #[arg(short='v', long="verbose")]
     pub verbose: bool,
+    /// never touch the network, build only from what is already in src/
+    #[arg(long="offline", global=true)]
+    pub offline: bool,
+    /// how many sources to fetch at once
+    #[arg(short='j', long="jobs", global=true, default_value_t=4)]
+    pub jobs: usize,
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Debug,Subcommand)]
 pub enum Commands {
+    /// download every source into src/
+    Fetch {
+        path: Option<PathBuf>
+    },
+    /// extract the sources into build/ and apply patches
+    Prepare {
+        path: Option<PathBuf>
+    },
+    /// configure and compile, running fetch and prepare first if they are out of date
     #[command(alias="b")]
     Build {
         path: Option<PathBuf>
+    },
+    /// install into pkg/ and create the faepkgs in out/
+    #[command(alias="p")]
+    Package {
+        path: Option<PathBuf>
+    },
+    /// check a recipe without fetching anything, exits 1 on warnings and 2 on errors
+    #[command(alias="l")]
+    Lint {
+        path: Option<PathBuf>
+    },
+    /// manage the source cache shared between recipes
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// download every source with a url and write its checksums into faebuild.yaml
+    Updsums {
+        path: Option<PathBuf>,
+        /// only report mismatched sums, exits 1 if there are any
+        #[arg(long)]
+        check: bool,
+    }
+}
+
+#[derive(Debug,Subcommand)]
+pub enum CacheCommands {
+    /// remove cached sources and git mirrors not used by any recipe under tree
+    Gc {
+        tree: PathBuf,
+        /// only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
     }
 }
\ No newline at end of file
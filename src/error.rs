@@ -0,0 +1,108 @@
+//this is the error a build fails with, the variant says which part of it went wrong and the causes under it say why, main prints the whole chain once as the report
+use crate::utils::checksum::Algorithm;
+use std::{fmt::Write, io, path::PathBuf};
+use thiserror::Error;
+
+/// a result that fails with a build [`enum@Error`] unless told otherwise
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// why a build failed, what went wrong underneath is in the source chain
+#[derive(Debug, Error)]
+pub enum Error {
+    /// the recipe or the user config can't be read or isn't valid, the cause carries the
+    /// file:line:column of every problem
+    #[error(transparent)]
+    Config(anyhow::Error),
+    /// a source couldn't be downloaded, copied or cloned
+    #[error("failed to fetch {what}")]
+    Fetch {
+        /// the source as the recipe lists it
+        what: String,
+        /// why it failed
+        #[source]
+        cause: anyhow::Error,
+    },
+    /// a fetched source isn't what the recipe pins it to
+    #[error("{} from {origin} doesn't match its {algorithm}\n  expected {expected}\n  actual   {actual}", .file.display())]
+    Checksum {
+        /// the fetched file
+        file: PathBuf,
+        /// the url it was downloaded from, or the first url or path the recipe gives
+        origin: String,
+        /// the checksum that didn't match
+        algorithm: Algorithm,
+        /// the sum the recipe pins
+        expected: String,
+        /// the sum of the file
+        actual: String,
+    },
+    /// a source isn't signed by one of the keys the recipe trusts
+    #[error("failed to verify the signature of {}", .file.display())]
+    Signature {
+        /// the signed file, or the repository of a signed git source
+        file: PathBuf,
+        /// why the signature was rejected
+        #[source]
+        cause: anyhow::Error,
+    },
+    /// an archive source couldn't be unpacked or the guard refused one of its entries
+    #[error("failed to extract {}", .archive.display())]
+    Extract {
+        /// the archive as it was fetched
+        archive: PathBuf,
+        /// why it failed
+        #[source]
+        cause: anyhow::Error,
+    },
+    /// a patch didn't apply
+    #[error("failed to apply {}", .patch.display())]
+    Patch {
+        /// the patch file
+        patch: PathBuf,
+        /// why it failed
+        #[source]
+        cause: anyhow::Error,
+    },
+    /// a configure, build or install step, or the source directory they run in
+    #[error("failed to build {name}")]
+    Build {
+        /// the package with its version and release
+        name: String,
+        /// the step that failed and why
+        #[source]
+        cause: anyhow::Error,
+    },
+    /// the staged install couldn't be split up or written out as faepkgs
+    #[error("failed to package {name}")]
+    Package {
+        /// the package with its version and release
+        name: String,
+        /// why it failed
+        #[source]
+        cause: anyhow::Error,
+    },
+    /// reading or writing a file outside of any one part of the build failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// anything that doesn't belong to one of the parts above
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Error {
+    /// the error followed by everything that caused it, one per line
+    pub fn report(&self) -> String {
+        let mut report = format!("Error: {self}");
+        let mut cause = std::error::Error::source(self);
+        if cause.is_some() {
+            report.push_str("\n\nCaused by:");
+        }
+        while let Some(e) = cause {
+            for line in e.to_string().lines() {
+                let _ = write!(report, "\n    {line}");
+            }
+            cause = e.source();
+        }
+        report
+    }
+}
@@ -0,0 +1,69 @@
+//this implements faebuild cache gc, it prunes cache entries that no recipe under a tree refers to anymore
+use anyhow::Result;
+use faebuild::{
+    buildconfig::{BuildConfig, SourceType},
+    userconfig::UserConfig,
+    utils::cache::{url_key, Cache},
+};
+use std::{
+    collections::HashSet,
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+};
+
+// every faebuild.yaml under dir, skipping hidden directories such as .git
+fn find_recipes(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                find_recipes(&path, out)?;
+            }
+        } else if file_type.is_file() && entry.file_name() == "faebuild.yaml" {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn run(cache: &Cache, user_config: &UserConfig, tree: &Path, dry_run: bool) -> Result<()> {
+    let mut recipes = vec![];
+    find_recipes(tree, &mut recipes)?;
+
+    let mut keep_sources = HashSet::new();
+    let mut keep_mirrors = HashSet::new();
+    for recipe in &recipes {
+        // a recipe we can't read would make us drop sources it still needs, so stop instead
+        let config = BuildConfig::parse(recipe, &read_to_string(recipe)?)?;
+        for source in &config.sources {
+            if let Some(key) = source.cache_key() {
+                keep_sources.insert(key);
+            }
+            if let SourceType::Git = source.r#type {
+                // every url and mirror the source may have been cloned from has its own mirror
+                for url in user_config.candidates(source.urls()) {
+                    keep_mirrors.insert(url_key(&url));
+                }
+            }
+        }
+    }
+
+    let removed = cache.gc(&keep_sources, &keep_mirrors, dry_run)?;
+    for path in &removed {
+        if dry_run {
+            println!("would remove {}", path.display());
+        } else {
+            println!("removed {}", path.display());
+        }
+    }
+    println!(
+        "{} recipes reference {} sources and {} git mirrors, {} cache entries unreferenced",
+        recipes.len(),
+        keep_sources.len(),
+        keep_mirrors.len(),
+        removed.len()
+    );
+    Ok(())
+}
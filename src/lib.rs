@@ -0,0 +1,52 @@
+//! faebuild builds faepkgs from faebuild.yaml recipes, this is everything the faebuild command
+//! does without the command line around it.
+//!
+//! a recipe is read with [`BuildConfig::load`], which checks it the way faebuild lint does, its
+//! sources are fetched one at a time with [`Sources::fetch`] and a whole build is run phase by
+//! phase through a [`Context`]:
+//!
+//! ```no_run
+//! use faebuild::{Context, FetchOptions, Phase, Quiet, UserConfig};
+//! use std::{path::Path, sync::Arc};
+//!
+//! # async fn build() -> faebuild::Result<()> {
+//! let options = FetchOptions {
+//!     user_config: UserConfig::load()?,
+//!     progress: Arc::new(Quiet),
+//!     jobs: 4,
+//!     ..Default::default()
+//! };
+//! Context::new(Path::new("recipes/foo"), options)
+//!     .await?
+//!     .run(Phase::Package)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! nothing here prints on its own, what a build is doing goes to the [`Progress`] in
+//! [`FetchOptions::progress`]. the command line passes a [`Terminal`] and [`Quiet`] drops all of
+//! it. failures come back as an [`Error`] saying which part of the build went wrong
+#![warn(missing_docs)]
+
+mod build;
+/// the faebuild.yaml recipe, how it is checked and how its sources are fetched and verified
+pub mod buildconfig;
+/// the error a build fails with
+pub mod error;
+mod package;
+/// the fetch, prepare, build and package phases and the stamps that record them
+pub mod phases;
+/// how a build reports what it is doing while it does it
+pub mod progress;
+/// the per user config in ~/.config/faebuild/config.yaml
+pub mod userconfig;
+/// the network, cache, checksum, signature and archive helpers the phases are built from
+pub mod utils;
+
+pub use buildconfig::{BuildConfig, FetchOptions, SourceType, Sources};
+pub use error::{Error, Result};
+pub use phases::{Context, Phase};
+pub use progress::{Progress, Quiet, Terminal, Transfer};
+pub use userconfig::UserConfig;
+pub use utils::{download_with_pb, patch};
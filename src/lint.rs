@@ -0,0 +1,144 @@
+//this implements faebuild lint, checks for recipe mistakes that don't stop a build but are worth fixing
+use anyhow::{Context, Result};
+use faebuild::{
+    buildconfig::{BuildConfig, Problem, SourceType, SourceUrl},
+    utils::spans::Spans,
+};
+use std::{collections::HashMap, fs::read_to_string, path::Path, process::ExitCode};
+use url::Url;
+
+pub const KNOWN_ARCHES: &[&str] = &[
+    "any",
+    "x86_64",
+    "aarch64",
+    "i686",
+    "armv7h",
+    "riscv64",
+    "ppc64le",
+    "s390x",
+    "loongarch64",
+];
+
+// exit codes, so CI can fail on errors and optionally on warnings
+const EXIT_WARNINGS: u8 = 1;
+const EXIT_ERRORS: u8 = 2;
+
+fn check_url(problems: &mut Vec<Problem>, path: String, url: &Url) {
+    if matches!(url.scheme(), "http" | "ftp") {
+        problems.push(Problem::new(
+            path,
+            format!(
+                "{url} uses plain {}, use https if the host supports it",
+                url.scheme()
+            ),
+        ));
+    }
+}
+
+pub fn lint(config: &BuildConfig) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    if let Err(e) = spdx::Expression::parse(&config.license) {
+        let message = match spdx::Expression::canonicalize(&config.license) {
+            Ok(Some(canonical)) => format!(
+                "license {} is not a valid SPDX expression, did you mean {canonical}?",
+                config.license
+            ),
+            _ => format!(
+                "license {} is not a valid SPDX expression: {}",
+                config.license, e.reason
+            ),
+        };
+        problems.push(Problem::new("license", message));
+    }
+
+    if config.version.starts_with('v') {
+        problems.push(Problem::new(
+            "version",
+            format!(
+                "version {} has a leading v, use {}",
+                config.version,
+                &config.version[1..]
+            ),
+        ));
+    }
+
+    for (i, arch) in config.arch.iter().enumerate() {
+        if !KNOWN_ARCHES.contains(&arch.as_str()) {
+            problems.push(Problem::new(
+                format!("arch[{i}]"),
+                format!(
+                    "unknown arch {arch}, expected one of {}",
+                    KNOWN_ARCHES.join(", ")
+                ),
+            ));
+        }
+    }
+
+    check_url(&mut problems, "url".to_owned(), &config.url);
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, source) in config.sources.iter().enumerate() {
+        let path = format!("sources[{i}]");
+        match &source.url {
+            Some(SourceUrl::Single(url)) => check_url(&mut problems, format!("{path}.url"), url),
+            Some(SourceUrl::Multiple(urls)) => {
+                for (j, url) in urls.iter().enumerate() {
+                    check_url(&mut problems, format!("{path}.url[{j}]"), url);
+                }
+            }
+            None => {}
+        }
+        if matches!(source.r#type, SourceType::Git) && source.tag.is_none() {
+            problems.push(Problem::new(
+                &path,
+                "git source has no tag, pinning a tag documents which release the commit is",
+            ));
+        }
+        let key = match (source.url(), &source.path) {
+            (Some(url), _) => url.to_string(),
+            (None, Some(file)) => file.display().to_string(),
+            (None, None) => continue,
+        };
+        if let Some(first) = seen.get(&key) {
+            problems.push(Problem::new(
+                &path,
+                format!("{key} is already used by sources[{first}]"),
+            ));
+        } else {
+            seen.insert(key, i);
+        }
+    }
+
+    problems
+}
+
+// lints the recipe at path, printing every finding and returning the exit code for them
+pub fn run(path: &Path) -> Result<ExitCode> {
+    let text = read_to_string(path).context(format!("failed to read {}", path.display()))?;
+    let config = match BuildConfig::parse(path, &text) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return Ok(ExitCode::from(EXIT_ERRORS));
+        }
+    };
+    let spans = Spans::parse(&text);
+
+    let errors = config.validate();
+    let warnings = lint(&config);
+    for problem in &errors {
+        eprintln!("error: {}", problem.report(path, &spans));
+    }
+    for problem in &warnings {
+        eprintln!("warning: {}", problem.report(path, &spans));
+    }
+
+    if !errors.is_empty() {
+        Ok(ExitCode::from(EXIT_ERRORS))
+    } else if !warnings.is_empty() {
+        Ok(ExitCode::from(EXIT_WARNINGS))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
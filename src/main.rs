@@ -1,64 +1,98 @@
-mod buildconfig;
 mod cli;
-mod utils;
-use anyhow::{anyhow, Result};
-use buildconfig::BuildConfig;
+mod gc;
+mod lint;
+mod updsums;
+use anyhow::anyhow;
 use clap::Parser;
-use cli::{Cli, Commands};
-use serde_yaml::from_reader;
-use std::{
-    fs::{create_dir, remove_dir_all, File},
-    path::PathBuf,
+use cli::{CacheCommands, Cli, Commands};
+use faebuild::{
+    utils::{cache::Cache, network::Network},
+    Context, Error, FetchOptions, Phase, Result, Terminal, UserConfig,
 };
+use std::{path::PathBuf, process::ExitCode, sync::Arc};
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Cli::parse();
-    match args.command {
-        Commands::Build { path } => {
-            let builddir = path.unwrap_or(PathBuf::from(".")).canonicalize()?;
-            if builddir.exists() {
-                let buildconfig = builddir.join("faebuild.yaml");
-                if !buildconfig.exists() {
-                    return Err(anyhow!("failed to find faebuild.yaml, does it exist?"));
-                }
-
-                let workdir = builddir.join("build");
-                let srcdir = builddir.join("src");
-                if workdir.exists() {
-                    remove_dir_all(&workdir).unwrap();
-                }
-                create_dir(&workdir).expect("failed to create dir build");
-                if !srcdir.exists() {
-                    create_dir(&srcdir).expect("failed to create directory src");
-                }
-
-                let file = File::open(buildconfig).unwrap();
+fn resolve_builddir(path: Option<PathBuf>, verbose: bool) -> Result<PathBuf> {
+    let path = path.unwrap_or(PathBuf::from("."));
+    match path.canonicalize() {
+        Ok(builddir) => Ok(builddir),
+        Err(_) => {
+            if verbose {
+                eprintln!("DEBUG RESOLVED DIR: {}", path.display());
+            }
+            Err(Error::Config(anyhow!(
+                "failed to find directory, does it exist?"
+            )))
+        }
+    }
+}
 
-                let config: BuildConfig = from_reader(file).unwrap();
-                let mut patches: Vec<PathBuf> = vec![];
+// every error ends up here and is reported once
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e.report());
+            ExitCode::FAILURE
+        }
+    }
+}
 
-                for source in config.sources {
-                    match source.r#type {
-                        buildconfig::SourceType::Patch => {
-                            let path = source.fetch(&srcdir, &workdir).await.unwrap();
-                            patches.append(&mut vec![path]);
-                        }
-                        _ => {
-                            source.fetch(&srcdir, &workdir).await.unwrap();
-                        }
-                    }
-                }
-                if !patches.is_empty() {
-                    utils::patch(patches, &workdir)?;
-                }
-            } else {
-                if args.verbose {
-                    eprintln!("DEBUG RESOLVED DIR: {}", builddir.display());
-                }
-                return Err(anyhow!("failed to find directory, does it exist?"));
+async fn run(args: Cli) -> Result<ExitCode> {
+    let (phase, path) = match args.command {
+        Commands::Fetch { path } => (Phase::Fetch, path),
+        Commands::Prepare { path } => (Phase::Prepare, path),
+        Commands::Build { path } => (Phase::Build, path),
+        Commands::Package { path } => (Phase::Package, path),
+        Commands::Lint { path } => {
+            let recipe = resolve_builddir(path, args.verbose)?.join("faebuild.yaml");
+            if !recipe.exists() {
+                return Err(Error::Config(anyhow!(
+                    "failed to find faebuild.yaml, does it exist?"
+                )));
             }
+            return Ok(lint::run(&recipe)?);
         }
-    }
-    Ok(())
+        Commands::Cache {
+            command: CacheCommands::Gc { tree, dry_run },
+        } => {
+            let cache = Cache::new(Cache::default_root()?);
+            let tree = resolve_builddir(Some(tree), args.verbose)?;
+            gc::run(&cache, &UserConfig::load()?, &tree, dry_run)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Updsums { path, check } => {
+            let builddir = resolve_builddir(path, args.verbose)?;
+            let recipe = builddir.join("faebuild.yaml");
+            if !recipe.exists() {
+                return Err(Error::Config(anyhow!(
+                    "failed to find faebuild.yaml, does it exist?"
+                )));
+            }
+            let user_config = UserConfig::load()?;
+            let fetch_options = FetchOptions {
+                network: Network::new(&user_config)?,
+                user_config,
+                progress: Arc::new(Terminal::new()),
+                ..Default::default()
+            };
+            return Ok(updsums::run(&recipe, &builddir.join("src"), &fetch_options, check).await?);
+        }
+    };
+
+    let builddir = resolve_builddir(path, args.verbose)?;
+    let user_config = UserConfig::load()?;
+    let fetch_options = FetchOptions {
+        offline: args.offline,
+        cache: Some(Cache::new(Cache::default_root()?)),
+        network: Network::new(&user_config)?,
+        user_config,
+        jobs: args.jobs,
+        progress: Arc::new(Terminal::new()),
+    };
+    Context::new(&builddir, fetch_options)
+        .await?
+        .run(phase)
+        .await?;
+    Ok(ExitCode::SUCCESS)
 }
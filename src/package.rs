@@ -0,0 +1,427 @@
+//this packs a staged pkgdir into a faepkg, a zstd compressed tarball carrying the package metadata and a file manifest next to the files
+use super::{
+    build::Builder,
+    buildconfig::{is_plain_name, BuildConfig, Permission},
+    progress::Progress,
+    utils::calculate_sha56sum,
+};
+use anyhow::{anyhow, Context, Result};
+use glob::Pattern;
+use serde::Serialize;
+use std::{
+    env::consts::ARCH,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    os::unix::fs::{lchown, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use tar::{EntryType, Header, HeaderMode};
+use url::Url;
+
+pub const PKGINFO: &str = ".PKGINFO";
+pub const MANIFEST: &str = ".MANIFEST";
+const EXTENSION: &str = "faepkg";
+
+#[derive(Debug, Serialize)]
+pub struct PkgInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub version: String,
+    pub rel: u32,
+    pub arch: String,
+    pub url: Url,
+    pub license: String,
+    pub depends: Vec<String>,
+    pub builddepends: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub r#type: FileType,
+    pub mode: u32,
+    pub owner: String,
+    pub group: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256sum: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl PkgInfo {
+    // the main package falls back to the top level description and depends, split packages
+    // only get what their own packages entry declares
+    pub fn new(config: &BuildConfig, name: &str) -> Result<Self> {
+        let main = *config
+            .name
+            .names()
+            .first()
+            .ok_or(anyhow!("name doesn't list any packages"))?
+            == name;
+        let subpackage = config.subpackage(name);
+        let description = match subpackage.and_then(|package| package.description.clone()) {
+            Some(description) => Some(description),
+            None if main => config.description.clone(),
+            None => None,
+        };
+        let depends = match subpackage.and_then(|package| package.depends.clone()) {
+            Some(depends) => depends,
+            None if main => config.depends.clone().unwrap_or_default(),
+            None => vec![],
+        };
+        Ok(PkgInfo {
+            name: name.to_owned(),
+            description,
+            version: config.version.clone(),
+            rel: config.rel,
+            arch: package_arch(&config.arch)?,
+            url: config.url.clone(),
+            license: config.license.clone(),
+            depends,
+            builddepends: config.builddepends.clone().unwrap_or_default(),
+        })
+    }
+
+    pub fn filename(&self) -> String {
+        format!(
+            "{}-{}-{}-{}.{EXTENSION}",
+            self.name, self.version, self.rel, self.arch
+        )
+    }
+}
+
+// arch independent packages are marked any, everything else is built for the host
+fn package_arch(arches: &[String]) -> Result<String> {
+    if arches.iter().any(|arch| arch == "any") {
+        return Ok("any".to_owned());
+    }
+    if arches.iter().any(|arch| arch == ARCH) {
+        return Ok(ARCH.to_owned());
+    }
+    Err(anyhow!(
+        "package does not support building on {ARCH}, supported arches are {}",
+        arches.join(", ")
+    ))
+}
+
+// every path under root, sorted so that packages are reproducible
+fn walk(root: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        out.push(path.strip_prefix(root)?.to_owned());
+        if path.is_dir() && !path.is_symlink() {
+            walk(root, &path, out)?;
+        }
+    }
+    Ok(())
+}
+
+// looks up the numeric id for name in an /etc/passwd or /etc/group style database, root is 0
+// even where the database doesn't list it
+fn lookup_id(db: &str, name: &str) -> Result<u32> {
+    if name == "root" {
+        return Ok(0);
+    }
+    fs::read_to_string(db)
+        .context(format!("failed to read {db}"))?
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(':');
+            if fields.next()? != name {
+                return None;
+            }
+            fields.nth(1)?.parse().ok()
+        })
+        .ok_or_else(|| anyhow!("{name} is not listed in {db}"))
+}
+
+// where permission points in pkgdir, refusing anything that leads outside of it. the last
+// component isn't followed so a symlink can still be given an owner
+fn permission_path(pkgdir: &Path, permission: &Permission) -> Result<PathBuf> {
+    let path = pkgdir.join(permission.relative_path());
+    let missing = || {
+        format!(
+            "permissions path {} was not installed",
+            permission.path.display()
+        )
+    };
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(anyhow!(missing()));
+    };
+    let real = parent.canonicalize().with_context(missing)?.join(name);
+    if !real.starts_with(pkgdir.canonicalize()?) {
+        return Err(anyhow!(
+            "permissions path {} leads outside the package",
+            permission.path.display()
+        ));
+    }
+    Ok(real)
+}
+
+// applies the mode and special bits of every entry to the staged files, ownership is only
+// changed when we are privileged enough to do so and is always recorded in the manifest
+pub fn apply_permissions(pkgdir: &Path, permissions: &[&Permission]) -> Result<()> {
+    for permission in permissions {
+        let path = permission_path(pkgdir, permission)?;
+        let metadata = fs::symlink_metadata(&path).context(format!(
+            "permissions path {} was not installed",
+            permission.path.display()
+        ))?;
+        if permission.owner.is_some() || permission.group.is_some() {
+            let uid = permission
+                .owner
+                .as_deref()
+                .map(|owner| lookup_id("/etc/passwd", owner))
+                .transpose()?;
+            let gid = permission
+                .group
+                .as_deref()
+                .map(|group| lookup_id("/etc/group", group))
+                .transpose()?;
+            match lchown(&path, uid, gid) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // chown clears setuid and setgid, so the mode goes on last
+        if !metadata.is_symlink() {
+            let mode = permission.apply(metadata.permissions().mode());
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn manifest(pkgdir: &Path, permissions: &[&Permission]) -> Result<Vec<ManifestEntry>> {
+    let mut paths = vec![];
+    walk(pkgdir, pkgdir, &mut paths)?;
+
+    let mut entries = vec![];
+    for path in paths {
+        let full = pkgdir.join(&path);
+        let metadata = fs::symlink_metadata(&full)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+        let permission = permissions
+            .iter()
+            .find(|permission| permission.relative_path() == path);
+        let owner = permission
+            .and_then(|permission| permission.owner.clone())
+            .unwrap_or("root".to_owned());
+        let group = permission
+            .and_then(|permission| permission.group.clone())
+            .unwrap_or("root".to_owned());
+        let path = path.to_string_lossy().to_string();
+        let (r#type, sha256sum, target) = if metadata.is_symlink() {
+            let target = fs::read_link(&full)?.to_string_lossy().to_string();
+            (FileType::Symlink, None, Some(target))
+        } else if metadata.is_dir() {
+            (FileType::Dir, None, None)
+        } else {
+            (FileType::File, Some(calculate_sha56sum(&full).await?), None)
+        };
+        entries.push(ManifestEntry {
+            path,
+            r#type,
+            mode,
+            owner,
+            group,
+            sha256sum,
+            target,
+        });
+    }
+    Ok(entries)
+}
+
+fn append_data<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+// packs pkgdir into outdir and returns the path of the created faepkg
+pub async fn create_package(
+    pkginfo: &PkgInfo,
+    pkgdir: &Path,
+    outdir: &Path,
+    permissions: &[&Permission],
+) -> Result<PathBuf> {
+    apply_permissions(pkgdir, permissions)?;
+    let manifest = manifest(pkgdir, permissions).await?;
+    if !outdir.exists() {
+        fs::create_dir_all(outdir)?;
+    }
+    // name and version come straight from the recipe, the package has to land directly in outdir
+    let filename = pkginfo.filename();
+    if !is_plain_name(&filename) {
+        return Err(anyhow!("{filename:?} is not a valid package file name"));
+    }
+    let out = outdir.join(filename);
+    let file = File::create(&out).context(format!("Failed to create {}", out.display()))?;
+    let encoder = zstd::Encoder::new(file, 19)?;
+    let mut builder = tar::Builder::new(encoder);
+
+    append_data(
+        &mut builder,
+        PKGINFO,
+        serde_yaml::to_string(pkginfo)?.as_bytes(),
+    )?;
+    append_data(
+        &mut builder,
+        MANIFEST,
+        serde_yaml::to_string(&manifest)?.as_bytes(),
+    )?;
+    // headers are written by hand so the archive carries the manifest ownership rather than
+    // whoever ran the build
+    for entry in &manifest {
+        let full = pkgdir.join(&entry.path);
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&fs::symlink_metadata(&full)?, HeaderMode::Complete);
+        header.set_mode(entry.mode);
+        header.set_uid(lookup_id("/etc/passwd", &entry.owner)?.into());
+        header.set_gid(lookup_id("/etc/group", &entry.group)?.into());
+        header.set_username(&entry.owner)?;
+        header.set_groupname(&entry.group)?;
+        match entry.r#type {
+            FileType::File => builder.append_data(&mut header, &entry.path, File::open(&full)?)?,
+            FileType::Dir => builder.append_data(&mut header, &entry.path, io::empty())?,
+            FileType::Symlink => {
+                let target = entry.target.as_deref().unwrap_or_default();
+                builder.append_link(&mut header, &entry.path, target)?
+            }
+        }
+    }
+
+    builder.into_inner()?.finish()?;
+    Ok(out)
+}
+
+// moves every path under pkgdir matching one of globs into dest, directories are moved whole
+fn split_files(pkgdir: &Path, dest: &Path, globs: &[String]) -> Result<usize> {
+    let patterns = globs
+        .iter()
+        .map(|glob| Pattern::new(glob.trim_start_matches('/')))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut paths = vec![];
+    walk(pkgdir, pkgdir, &mut paths)?;
+
+    let mut moved: Vec<PathBuf> = vec![];
+    for path in paths {
+        if moved.iter().any(|parent| path.starts_with(parent)) {
+            continue;
+        }
+        if !patterns.iter().any(|pattern| pattern.matches_path(&path)) {
+            continue;
+        }
+        let target = dest.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(pkgdir.join(&path), &target)?;
+        moved.push(path);
+    }
+
+    // drop directories that only existed to hold the files we just moved
+    for path in &moved {
+        for parent in path.ancestors().skip(1) {
+            let dir = pkgdir.join(parent);
+            if dir == pkgdir || fs::read_dir(&dir)?.next().is_some() {
+                break;
+            }
+            fs::remove_dir(&dir)?;
+        }
+    }
+    Ok(moved.len())
+}
+
+// distributes the staged install between every package in name and packs each of them,
+// split packages are carved out in declaration order and the first name keeps the rest
+pub async fn create_packages(
+    config: &BuildConfig,
+    builder: &Builder<'_>,
+    pkgdir: &Path,
+    splitdir: &Path,
+    outdir: &Path,
+    progress: &dyn Progress,
+) -> Result<Vec<PathBuf>> {
+    let names = config.name.names();
+    let main = *names
+        .first()
+        .ok_or(anyhow!("name doesn't list any packages"))?;
+    if let Some(name) = names.iter().find(|name| !is_plain_name(name)) {
+        return Err(anyhow!("{name:?} is not a valid package name"));
+    }
+    let mut staged = vec![];
+    for name in names.iter().skip(1) {
+        let package = config
+            .subpackage(name)
+            .ok_or(anyhow!("split package {name} has no packages entry"))?;
+        let dest = splitdir.join(name);
+        if dest.exists() {
+            fs::remove_dir_all(&dest)?;
+        }
+        fs::create_dir_all(&dest)?;
+        if let Some(globs) = &package.files {
+            let moved = split_files(pkgdir, &dest, globs)?;
+            if moved == 0 {
+                progress.warning(&format!("files for {name} did not match anything"));
+            }
+        }
+        if let Some(steps) = &package.installsteps {
+            builder.install_steps(steps, &dest)?;
+        }
+        staged.push((*name, dest));
+    }
+    staged.insert(0, (main, pkgdir.to_owned()));
+
+    // each permissions entry belongs to whichever package ended up with its path
+    let permissions = config.permissions.as_deref().unwrap_or_default();
+    for permission in permissions {
+        if !staged.iter().any(|(_, dir)| {
+            dir.join(permission.relative_path())
+                .symlink_metadata()
+                .is_ok()
+        }) {
+            return Err(anyhow!(
+                "permissions path {} is not part of any package",
+                permission.path.display()
+            ));
+        }
+    }
+
+    let mut packages = vec![];
+    for (name, dir) in staged {
+        let pkginfo = PkgInfo::new(config, name)?;
+        let owned = permissions
+            .iter()
+            .filter(|permission| {
+                dir.join(permission.relative_path())
+                    .symlink_metadata()
+                    .is_ok()
+            })
+            .collect::<Vec<_>>();
+        packages.push(create_package(&pkginfo, &dir, outdir, &owned).await?);
+    }
+    Ok(packages)
+}
@@ -0,0 +1,291 @@
+//this splits a build into the fetch, prepare, build and package phases, each one records when it completed so that later phases can check their prerequisites
+use super::{
+    build::Builder,
+    buildconfig::{BuildConfig, FetchOptions, SourceType},
+    error::{Error, Result},
+    package::create_packages,
+    progress::Progress,
+    utils::{self, calculate_sha56sum, signature::Keyring},
+};
+use anyhow::anyhow;
+use futures::{stream, StreamExt};
+use std::{
+    fmt,
+    fs::{create_dir_all, read_to_string, remove_dir_all, remove_file, write},
+    path::{Path, PathBuf},
+};
+
+/// the steps of a build in the order they run, running one runs whichever of the earlier ones
+/// haven't completed for the current recipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// download, copy or clone every source into src
+    Fetch,
+    /// verify signatures, unpack the sources into build and apply the patches
+    Prepare,
+    /// configure and compile
+    Build,
+    /// install into pkg, split it up and write the faepkgs into out
+    Package,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [Phase::Fetch, Phase::Prepare, Phase::Build, Phase::Package];
+
+    fn previous(self) -> Option<Phase> {
+        match self {
+            Phase::Fetch => None,
+            Phase::Prepare => Some(Phase::Fetch),
+            Phase::Build => Some(Phase::Prepare),
+            Phase::Package => Some(Phase::Build),
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Fetch => write!(f, "fetch"),
+            Phase::Prepare => write!(f, "prepare"),
+            Phase::Build => write!(f, "build"),
+            Phase::Package => write!(f, "package"),
+        }
+    }
+}
+
+/// a recipe and the directories of its build, phases are run through it
+pub struct Context {
+    /// the validated recipe
+    pub config: BuildConfig,
+    /// the directory holding faebuild.yaml, everything else is below it
+    pub builddir: PathBuf,
+    /// fetched sources
+    pub srcdir: PathBuf,
+    /// where the sources are unpacked and built
+    pub workdir: PathBuf,
+    /// the staged install of the main package
+    pub pkgdir: PathBuf,
+    /// the staged installs of split packages, one directory each
+    pub splitdir: PathBuf,
+    /// the finished faepkgs
+    pub outdir: PathBuf,
+    /// how sources are fetched and where progress is reported
+    pub fetch_options: FetchOptions,
+    statedir: PathBuf,
+    // a phase only counts as complete for the recipe it ran against
+    recipe_sum: String,
+}
+
+impl Context {
+    /// loads builddir/faebuild.yaml, everything the build writes goes under builddir
+    pub async fn new(builddir: &Path, fetch_options: FetchOptions) -> Result<Self> {
+        let recipe = builddir.join("faebuild.yaml");
+        if !recipe.exists() {
+            return Err(Error::Config(anyhow!(
+                "failed to find faebuild.yaml, does it exist?"
+            )));
+        }
+        Ok(Context {
+            config: BuildConfig::load(&recipe)?,
+            builddir: builddir.to_owned(),
+            srcdir: builddir.join("src"),
+            workdir: builddir.join("build"),
+            pkgdir: builddir.join("pkg"),
+            splitdir: builddir.join("split"),
+            outdir: builddir.join("out"),
+            fetch_options,
+            statedir: builddir.join(".faebuild"),
+            recipe_sum: calculate_sha56sum(&recipe).await?,
+        })
+    }
+
+    fn stamp(&self, phase: Phase) -> PathBuf {
+        self.statedir.join(format!("{phase}.done"))
+    }
+
+    /// whether phase last completed for the recipe as it is now
+    pub fn is_complete(&self, phase: Phase) -> bool {
+        match read_to_string(self.stamp(phase)) {
+            Ok(sum) => sum.trim() == self.recipe_sum,
+            Err(_) => false,
+        }
+    }
+
+    // forgets phase and everything after it, their output is about to be replaced
+    fn invalidate_from(&self, phase: Phase) -> Result<()> {
+        for later in Phase::ALL.iter().skip_while(|p| **p != phase) {
+            let stamp = self.stamp(*later);
+            if stamp.exists() {
+                remove_file(stamp)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mark_complete(&self, phase: Phase) -> Result<()> {
+        create_dir_all(&self.statedir)?;
+        write(self.stamp(phase), &self.recipe_sum)?;
+        Ok(())
+    }
+
+    /// runs phase after any of its prerequisites that have not completed for this recipe
+    pub async fn run(&self, phase: Phase) -> Result<()> {
+        let mut pending = vec![phase];
+        let mut current = phase;
+        while let Some(previous) = current.previous() {
+            if self.is_complete(previous) {
+                break;
+            }
+            pending.push(previous);
+            current = previous;
+        }
+        for phase in pending.into_iter().rev() {
+            self.progress().message(&format!("Running {phase}"));
+            self.invalidate_from(phase)?;
+            match phase {
+                Phase::Fetch => self.fetch().await?,
+                Phase::Prepare => self.prepare().await?,
+                Phase::Build => self.build()?,
+                Phase::Package => self.package().await?,
+            }
+            self.mark_complete(phase)?;
+        }
+        Ok(())
+    }
+
+    fn progress(&self) -> &dyn Progress {
+        &*self.fetch_options.progress
+    }
+
+    // the directory builds and patches run in
+    fn sourcedir(&self) -> PathBuf {
+        match &self.config.subdir {
+            Some(subdir) => self.workdir.join(subdir),
+            None => self.workdir.clone(),
+        }
+    }
+
+    // fetches up to jobs sources at once, results still come back in declared order
+    async fn fetch(&self) -> Result<()> {
+        create_dir_all(&self.srcdir)?;
+        let offline = self.fetch_options.offline;
+        let mut fetches = stream::iter(&self.config.sources)
+            .map(|source| async move {
+                let fetched = source
+                    .fetch(&self.srcdir, &self.builddir, &self.fetch_options)
+                    .await;
+                (source, fetched)
+            })
+            .buffered(self.fetch_options.jobs.max(1));
+        let mut missing = vec![];
+        while let Some((source, fetched)) = fetches.next().await {
+            match fetched {
+                Ok(_) => {}
+                // offline we want the whole list of what to fetch, not just the first gap
+                Err(e) if !offline => return Err(e),
+                Err(Error::Fetch { cause, .. }) => missing.push(format!("  {source}: {cause}")),
+                Err(e) => missing.push(format!("  {source}: {e}")),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::Other(anyhow!(
+                "{} sources are not available offline:\n{}",
+                missing.len(),
+                missing.join("\n")
+            )));
+        }
+        Ok(())
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        if self.workdir.exists() {
+            remove_dir_all(&self.workdir)?;
+        }
+        create_dir_all(&self.workdir)?;
+
+        let keyring = Keyring::load(
+            &self.builddir.join("keys"),
+            self.config.validpgpkeys.as_deref().unwrap_or_default(),
+        )?;
+        let mut patches: Vec<PathBuf> = vec![];
+        for source in &self.config.sources {
+            let path = source
+                .extract(
+                    &self.srcdir,
+                    &self.workdir,
+                    &keyring,
+                    &self.fetch_options.user_config.extract,
+                    self.progress(),
+                )
+                .await?;
+            if let SourceType::Patch = source.r#type {
+                patches.push(path);
+            }
+        }
+        if !patches.is_empty() {
+            utils::patch(patches, &self.sourcedir(), self.progress())?;
+        }
+        Ok(())
+    }
+
+    // name, version and rel, what build and package errors name the package by
+    fn package_name(&self) -> String {
+        format!(
+            "{} {}-{}",
+            self.config.name, self.config.version, self.config.rel
+        )
+    }
+
+    fn build(&self) -> Result<()> {
+        self.progress()
+            .message(&format!("Building {}", self.package_name()));
+        let build = || -> anyhow::Result<()> {
+            let builder = Builder::new(&self.config, &self.workdir, self.progress())?;
+            builder.configure()?;
+            builder.build()
+        };
+        build().map_err(|cause| Error::Build {
+            name: self.package_name(),
+            cause,
+        })
+    }
+
+    async fn package(&self) -> Result<()> {
+        if self.pkgdir.exists() {
+            remove_dir_all(&self.pkgdir)?;
+        }
+        create_dir_all(&self.pkgdir)?;
+
+        let builder =
+            Builder::new(&self.config, &self.workdir, self.progress()).map_err(|cause| {
+                Error::Build {
+                    name: self.package_name(),
+                    cause,
+                }
+            })?;
+        builder
+            .install(&self.pkgdir)
+            .map_err(|cause| Error::Build {
+                name: self.package_name(),
+                cause,
+            })?;
+        let packages = create_packages(
+            &self.config,
+            &builder,
+            &self.pkgdir,
+            &self.splitdir,
+            &self.outdir,
+            self.progress(),
+        )
+        .await
+        .map_err(|cause| Error::Package {
+            name: self.package_name(),
+            cause,
+        })?;
+        for package in packages {
+            self.progress()
+                .message(&format!("Created {}", package.display()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+//this is how a build reports what it is doing, the cli draws it on the terminal with indicatif and anything driving faebuild as a library can plug in its own
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{cmp::min, fmt};
+
+/// receives what a build is doing while it does it. fetches run in parallel so it is called
+/// from several tasks at once, and from the middle of downloads and build steps so nothing in
+/// it should block
+pub trait Progress: Send + Sync {
+    /// something the build started or finished, a phase, a build step or a package written
+    fn message(&self, message: &str);
+
+    /// a problem the build carries on from, such as a download being retried
+    fn warning(&self, message: &str);
+
+    /// a download or git fetch of name started, total is its size in bytes when it is known.
+    /// the returned [`Transfer`] is told how far it got
+    fn transfer(&self, name: &str, total: Option<u64>) -> Box<dyn Transfer>;
+}
+
+/// one download or git fetch in flight, it is dropped without [`Transfer::finish`] when the
+/// transfer fails
+pub trait Transfer: Send {
+    /// bytes have been received so far, resumed downloads start past zero
+    fn position(&mut self, bytes: u64);
+
+    /// the transfer is complete, message says where it went
+    fn finish(&mut self, message: &str);
+}
+
+impl fmt::Debug for dyn Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// reports nothing, the default for [`FetchOptions`](crate::FetchOptions)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Quiet;
+
+impl Progress for Quiet {
+    fn message(&self, _: &str) {}
+
+    fn warning(&self, _: &str) {}
+
+    fn transfer(&self, _: &str, _: Option<u64>) -> Box<dyn Transfer> {
+        Box::new(Quiet)
+    }
+}
+
+impl Transfer for Quiet {
+    fn position(&mut self, _: u64) {}
+
+    fn finish(&mut self, _: &str) {}
+}
+
+/// prints messages to stdout and warnings to stderr, every transfer gets its own progress bar
+/// so parallel downloads don't draw over each other
+#[derive(Debug, Default, Clone)]
+pub struct Terminal {
+    bars: MultiProgress,
+}
+
+impl Terminal {
+    /// a reporter with no progress bars drawn yet
+    pub fn new() -> Self {
+        Terminal::default()
+    }
+}
+
+fn progress_bar(total: Option<u64>) -> ProgressBar {
+    match total {
+        Some(total) => {
+            let pb = ProgressBar::new(total);
+            if let Ok(style) = ProgressStyle::default_bar()
+.template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.white/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+            {
+                pb.set_style(style.progress_chars("█  "));
+            }
+            pb
+        }
+        // without a length there is nothing to fill a bar with
+        None => {
+            let pb = ProgressBar::new_spinner();
+            if let Ok(style) = ProgressStyle::default_spinner()
+                .template("{msg}\n{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")
+            {
+                pb.set_style(style);
+            }
+            pb
+        }
+    }
+}
+
+impl Progress for Terminal {
+    fn message(&self, message: &str) {
+        self.bars.suspend(|| println!("==> {message}"));
+    }
+
+    fn warning(&self, message: &str) {
+        self.bars.suspend(|| eprintln!("warning: {message}"));
+    }
+
+    fn transfer(&self, name: &str, total: Option<u64>) -> Box<dyn Transfer> {
+        let pb = self.bars.add(progress_bar(total));
+        pb.set_message(name.to_owned());
+        Box::new(pb)
+    }
+}
+
+impl Transfer for ProgressBar {
+    fn position(&mut self, bytes: u64) {
+        self.set_position(match self.length() {
+            Some(total) => min(bytes, total),
+            None => bytes,
+        });
+    }
+
+    fn finish(&mut self, message: &str) {
+        self.finish_with_message(message.to_owned());
+    }
+}
@@ -0,0 +1,334 @@
+//this implements faebuild updsums, it downloads every source with a url and writes its checksums back into the recipe without touching comments or key order
+use anyhow::{anyhow, Context, Result};
+use faebuild::{
+    buildconfig::{BuildConfig, FetchOptions, SourceType},
+    utils::{
+        checksum::{hash_file, Algorithm},
+        spans::{Location, Spans},
+    },
+};
+use std::{
+    cmp::Reverse,
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+    process::ExitCode,
+};
+
+struct Update {
+    index: usize,
+    algorithm: Algorithm,
+    old: Option<String>,
+    new: String,
+}
+
+// end of the scalar starting at start, quoted scalars include their quotes and plain ones stop
+// at a comment or the end of the line
+fn scalar_end(text: &str, start: usize) -> usize {
+    let rest = &text[start..];
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+    if let Some(quote) = line.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        if let Some(close) = line[1..].find(quote) {
+            return start + close + 2;
+        }
+    }
+    let plain = match line.find(" #") {
+        Some(comment) => &line[..comment],
+        None => line,
+    };
+    start + plain.trim_end().len()
+}
+
+// rewrites every updated checksum, adding the key below url when it is missing
+fn rewrite(text: &str, updates: &[Update]) -> Result<String> {
+    let spans = Spans::parse(text);
+    let mut edits: Vec<(usize, usize, String)> = vec![];
+    for update in updates {
+        let path = format!("sources[{}]", update.index);
+        let field = update.algorithm.field();
+        // flow mappings like { url: ..., sha256sum: ... } would need their commas and braces kept
+        // intact, rather than guess at that leave them to the user
+        let source = spans
+            .value(&path)
+            .and_then(|location| location.offset(text))
+            .ok_or(anyhow!("failed to find {path}"))?;
+        if text[..source].trim_end().ends_with(['{', ',']) {
+            return Err(anyhow!(
+                "{path} is written as a flow mapping, updsums can only update sources written with one key per line"
+            ));
+        }
+        let key = spans.key(&format!("{path}.{field}"));
+        let value = spans
+            .value(&format!("{path}.{field}"))
+            .filter(|value| key.is_some_and(|key| key.line == value.line));
+        if let (Some(key), None) = (key, value) {
+            // a key without a value is null, yaml marks that on whatever comes next so put the
+            // sum straight after the colon instead
+            let start = key
+                .offset(text)
+                .ok_or(anyhow!("{field} of {path} is out of range"))?;
+            let line = &text[start..];
+            let colon = line[..line.find('\n').unwrap_or(line.len())]
+                .find(':')
+                .ok_or(anyhow!("failed to find the value of {field} in {path}"))?;
+            let at = start + colon + 1;
+            edits.push((at, at, format!(" {}", update.new)));
+        } else if let Some(location) = value {
+            let start = location
+                .offset(text)
+                .ok_or(anyhow!("{field} of {path} is out of range"))?;
+            let end = scalar_end(text, start);
+            let quote = match text[start..].chars().next() {
+                Some(quote @ ('"' | '\'')) => quote.to_string(),
+                _ => String::new(),
+            };
+            edits.push((start, end, format!("{quote}{}{quote}", update.new)));
+        } else {
+            let url = spans
+                .key(&format!("{path}.url"))
+                .ok_or(anyhow!("failed to find the url of {path}"))?;
+            // a list of urls may continue on the lines below the key
+            let last_line = (0..)
+                .map_while(|i| spans.value(&format!("{path}.url[{i}]")))
+                .map(|item| item.line)
+                .fold(url.line, usize::max);
+            let next_line = Location {
+                line: last_line + 1,
+                col: 1,
+            };
+            let (at, prefix) = match next_line.offset(text) {
+                Some(at) if at <= text.len() && text[..at].ends_with('\n') => (at, ""),
+                _ => (text.len(), "\n"),
+            };
+            let indent = " ".repeat(url.col - 1);
+            edits.push((at, at, format!("{prefix}{indent}{field}: {}\n", update.new)));
+        }
+    }
+
+    // apply from the back so earlier offsets stay valid
+    edits.sort_by_key(|edit| Reverse(edit.0));
+    let mut text = text.to_owned();
+    for (start, end, replacement) in edits {
+        text.replace_range(start..end, &replacement);
+    }
+    Ok(text)
+}
+
+// makes sure text still parses and every source carries exactly the sums config had with updates
+// applied, so a bad rewrite never reaches the recipe
+fn verify(recipe: &Path, text: &str, config: &BuildConfig, updates: &[Update]) -> Result<()> {
+    let rewritten = BuildConfig::parse(recipe, text)
+        .context("updating the checksums would leave the recipe unreadable, it was not changed")?;
+    if rewritten.sources.len() != config.sources.len() {
+        return Err(anyhow!(
+            "updating the checksums would change the number of sources, the recipe was not changed"
+        ));
+    }
+    for (index, (source, before)) in rewritten.sources.iter().zip(&config.sources).enumerate() {
+        for algorithm in Algorithm::ALL {
+            let expected = updates
+                .iter()
+                .find(|update| update.index == index && update.algorithm == algorithm)
+                .map(|update| update.new.as_str())
+                .or(before.checksum(algorithm));
+            if source.checksum(algorithm) != expected {
+                return Err(anyhow!(
+                    "failed to update the {algorithm} of sources[{index}], the recipe was not changed"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// downloads every source with a url into srcdir and updates or, with check, verifies recipe
+pub async fn run(
+    recipe: &Path,
+    srcdir: &Path,
+    options: &FetchOptions,
+    check: bool,
+) -> Result<ExitCode> {
+    let text = read_to_string(recipe).context(format!("failed to read {}", recipe.display()))?;
+    let config = BuildConfig::parse(recipe, &text)?;
+    create_dir_all(srcdir)?;
+
+    let mut updates = vec![];
+    for (index, source) in config.sources.iter().enumerate() {
+        if matches!(source.r#type, SourceType::Git) {
+            continue;
+        }
+        let Some(name) = source.download_name() else {
+            continue;
+        };
+        // always fetch fresh, a cached file would only tell us what we already pinned
+        let out = srcdir.join(name);
+        source.download(&out, options, false).await?;
+        // update whichever sums the source is pinned with, new sources get a sha256sum
+        let mut algorithms: Vec<Algorithm> = source
+            .checksums()
+            .iter()
+            .map(|(algorithm, _)| *algorithm)
+            .collect();
+        if algorithms.is_empty() {
+            algorithms.push(Algorithm::Sha256);
+        }
+        let sums = hash_file(&out, &algorithms)?;
+        for (algorithm, sum) in algorithms.into_iter().zip(sums) {
+            let old = source.checksum(algorithm);
+            if old != Some(sum.as_str()) {
+                updates.push(Update {
+                    index,
+                    algorithm,
+                    old: old.map(str::to_owned),
+                    new: sum,
+                });
+            }
+        }
+    }
+
+    for update in &updates {
+        let url = config.sources[update.index]
+            .url()
+            .map(|url| url.to_string())
+            .unwrap_or_default();
+        let url = format!("{url} {}", update.algorithm);
+        match &update.old {
+            Some(old) if check => println!(
+                "mismatch {url}\n  expected {old}\n  actual   {}",
+                update.new
+            ),
+            None if check => println!("missing  {url}\n  actual   {}", update.new),
+            Some(old) => println!("updated  {url}\n  {old} -> {}", update.new),
+            None => println!("added    {url}\n  {}", update.new),
+        }
+    }
+
+    if check {
+        if updates.is_empty() {
+            println!("all checksums match");
+            return Ok(ExitCode::SUCCESS);
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+    if !updates.is_empty() {
+        let rewritten = rewrite(&text, &updates)?;
+        verify(recipe, &rewritten, &config, &updates)?;
+        write(recipe, rewritten)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPE: &str = "\
+name: foo
+version: 1.0.0
+rel: 1
+arch: [any]
+url: https://example.org
+license: MIT
+buildtype: simple
+sources:
+";
+
+    fn sum(digit: char) -> String {
+        digit.to_string().repeat(64)
+    }
+
+    fn update(index: usize, old: Option<String>) -> Update {
+        Update {
+            index,
+            algorithm: Algorithm::Sha256,
+            old,
+            new: sum('b'),
+        }
+    }
+
+    // rewrites the sources below RECIPE and checks the result the way run does
+    fn rewritten(sources: &str, updates: &[Update]) -> Result<String> {
+        let text = format!("{RECIPE}{sources}");
+        let recipe = Path::new("faebuild.yaml");
+        let config = BuildConfig::parse(recipe, &text)?;
+        let rewritten = rewrite(&text, updates)?;
+        verify(recipe, &rewritten, &config, updates)?;
+        Ok(rewritten[RECIPE.len()..].to_owned())
+    }
+
+    #[test]
+    fn keeps_quotes_around_a_sum() {
+        let sources = format!(
+            "  - type: file\n    url: https://example.org/a\n    sha256sum: \"{}\"\n",
+            sum('a')
+        );
+        assert_eq!(
+            rewritten(&sources, &[update(0, Some(sum('a')))]).unwrap(),
+            sources.replace(&sum('a'), &sum('b'))
+        );
+    }
+
+    #[test]
+    fn keeps_a_comment_after_a_sum() {
+        let sources = format!(
+            "  - type: file\n    url: https://example.org/a\n    sha256sum: {} # from upstream\n",
+            sum('a')
+        );
+        assert_eq!(
+            rewritten(&sources, &[update(0, Some(sum('a')))]).unwrap(),
+            sources.replace(&sum('a'), &sum('b'))
+        );
+    }
+
+    #[test]
+    fn fills_in_an_empty_sum() {
+        let sources = "  - type: file\n    url: https://example.org/a\n    sha256sum:\n  - type: file\n    url: https://example.org/b\n    sha256sum: ~\n";
+        let expected = format!(
+            "  - type: file\n    url: https://example.org/a\n    sha256sum: {0}\n  - type: file\n    url: https://example.org/b\n    sha256sum: {0}\n",
+            sum('b')
+        );
+        assert_eq!(
+            rewritten(sources, &[update(0, None), update(1, None)]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn adds_a_missing_sum_after_a_url_list() {
+        let sources = "  - type: file\n    url:\n      - https://example.org/a\n      - https://mirror.example.org/a\n    extract_to: a\n  - type: file\n    url: https://example.org/b\n";
+        let expected = format!(
+            "  - type: file\n    url:\n      - https://example.org/a\n      - https://mirror.example.org/a\n    sha256sum: {0}\n    extract_to: a\n  - type: file\n    url: https://example.org/b\n    sha256sum: {0}\n",
+            sum('b')
+        );
+        assert_eq!(
+            rewritten(sources, &[update(0, None), update(1, None)]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn refuses_flow_mappings() {
+        let sources = format!(
+            "  - {{type: file, url: https://example.org/a, sha256sum: {}}}\n",
+            sum('a')
+        );
+        let error = rewritten(&sources, &[update(0, Some(sum('a')))]).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("sources[0] is written as a flow mapping"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn refuses_a_rewrite_that_loses_a_sum() {
+        let text = format!(
+            "{RECIPE}  - type: file\n    url: https://example.org/a\n    sha256sum: {}\n",
+            sum('a')
+        );
+        let recipe = Path::new("faebuild.yaml");
+        let config = BuildConfig::parse(recipe, &text).unwrap();
+        // the sum was never written
+        assert!(verify(recipe, &text, &config, &[update(0, Some(sum('a')))]).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//this is the per user config in ~/.config/faebuild/config.yaml, it holds settings that belong to the machine doing the build rather than to a recipe
+use crate::error::Error;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::{env, fmt, fs::read_to_string, path::PathBuf, time::Duration};
+use url::Url;
+
+/// settings of the machine doing the build, see config.yaml.example
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// every mirror that applies to a url is tried before it, in the order they are listed
+    #[serde(default)]
+    pub mirrors: Vec<Mirror>,
+    /// retries, timeouts and size limits of downloads
+    #[serde(default)]
+    pub download: DownloadSettings,
+    /// proxy, certificates and credentials
+    #[serde(default)]
+    pub network: NetworkSettings,
+    /// limits on what an archive may unpack to
+    #[serde(default)]
+    pub extract: ExtractSettings,
+}
+
+/// how hard a download is retried, the delay between attempts doubles up to max_backoff
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadSettings {
+    /// tries per url before moving on to the next one
+    pub attempts: u32,
+    /// seconds before the first retry
+    pub backoff: u64,
+    /// seconds the delay between retries stops doubling at
+    pub max_backoff: u64,
+    /// seconds a connection may take to open, 0 waits forever. it applies to git over http too
+    pub connect_timeout: u64,
+    /// seconds a download may go without receiving anything, 0 waits forever. it applies to git
+    /// over http too
+    pub read_timeout: u64,
+    /// seconds a single attempt may take in total, 0 for no limit. every attempt gets the full
+    /// timeout again, so a download can take up to attempts times as long plus the backoff
+    pub timeout: u64,
+    /// bytes, nothing larger is downloaded, 0 for no limit
+    pub max_size: u64,
+}
+
+/// how far an archive may unpack before it is taken for a decompression bomb, 0 for no limit
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractSettings {
+    /// bytes, summed over every file in the archive
+    pub max_size: u64,
+    /// entries of the archive, skipped ones included
+    pub max_entries: u64,
+}
+
+/// how downloads and git fetches reach the network, without a proxy the usual http_proxy and
+/// https_proxy variables and git's own config apply
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    /// every download and git fetch goes through this proxy
+    pub proxy: Option<Url>,
+    /// hosts reached directly even with a proxy, a leading dot matches subdomains
+    pub no_proxy: Vec<String>,
+    /// extra certificates trusted on top of the system ones
+    pub ca_bundle: Option<PathBuf>,
+    /// defaults to $NETRC or ~/.netrc
+    pub netrc: Option<PathBuf>,
+    /// credentials per host, these win over netrc
+    pub auth: Vec<HostAuth>,
+}
+
+/// credentials sent to host, either a bearer token or a username and password
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostAuth {
+    /// a host name, or host:port to only match that port
+    pub host: String,
+    /// sent as a bearer token
+    pub token: Option<String>,
+    /// sent as basic auth along with password
+    pub username: Option<String>,
+    /// see username
+    pub password: Option<String>,
+}
+
+/// urls starting with prefix are also looked for under each of urls, in order. the prefix can
+/// name a host (`https://ftp.gnu.org/gnu/`) or a pseudo scheme (`mirror://gnu/`) that recipes use
+/// instead of picking a host themselves
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mirror {
+    /// what a url has to start with for the mirror to apply
+    pub prefix: String,
+    /// replacements for the prefix, tried in order
+    pub urls: Vec<Url>,
+}
+
+/// the pseudo scheme that only resolves through mirrors, it is never downloaded from directly
+pub const MIRROR_SCHEME: &str = "mirror";
+
+fn push_unique(urls: &mut Vec<Url>, url: Url) {
+    if !urls.contains(&url) {
+        urls.push(url);
+    }
+}
+
+// keeps secrets out of anything that prints the config
+impl fmt::Debug for HostAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostAuth")
+            .field("host", &self.host)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            attempts: 4,
+            backoff: 1,
+            max_backoff: 30,
+            connect_timeout: 30,
+            read_timeout: 60,
+            timeout: 0,
+            max_size: 10 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for ExtractSettings {
+    fn default() -> Self {
+        ExtractSettings {
+            max_size: 32 * 1024 * 1024 * 1024,
+            max_entries: 1_000_000,
+        }
+    }
+}
+
+impl DownloadSettings {
+    /// a setting in seconds as a duration, None when it is turned off
+    pub fn duration(seconds: u64) -> Option<Duration> {
+        (seconds > 0).then(|| Duration::from_secs(seconds))
+    }
+}
+
+impl UserConfig {
+    /// $XDG_CONFIG_HOME/faebuild/config.yaml, falling back to ~/.config/faebuild/config.yaml
+    pub fn default_path() -> Result<PathBuf> {
+        if let Some(config) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            return Ok(PathBuf::from(config).join("faebuild").join("config.yaml"));
+        }
+        match env::var_os("HOME") {
+            Some(home) => Ok(PathBuf::from(home)
+                .join(".config")
+                .join("faebuild")
+                .join("config.yaml")),
+            None => Err(anyhow!(
+                "neither XDG_CONFIG_HOME nor HOME are set, can't find the user config"
+            )),
+        }
+    }
+
+    /// a missing config file is the same as an empty one
+    pub fn load() -> Result<UserConfig, Error> {
+        let path = UserConfig::default_path().map_err(Error::Config)?;
+        if !path.exists() {
+            return Ok(UserConfig::default());
+        }
+        let text = read_to_string(&path).context(format!("failed to read {}", path.display()));
+        text.and_then(|text| {
+            serde_yaml::from_str(&text).context(format!("failed to parse {}", path.display()))
+        })
+        .map_err(Error::Config)
+    }
+
+    /// every url a source listed as urls can be downloaded from, mirrors come before the url
+    /// they stand in for
+    pub fn candidates<'a>(&self, urls: impl IntoIterator<Item = &'a Url>) -> Vec<Url> {
+        let mut candidates = vec![];
+        for url in urls {
+            for mirror in &self.mirrors {
+                let Some(rest) = url.as_str().strip_prefix(&mirror.prefix) else {
+                    continue;
+                };
+                for base in &mirror.urls {
+                    // a base without a trailing slash would drop its last segment on join
+                    let base = match base.as_str().ends_with('/') {
+                        true => base.clone(),
+                        false => Url::parse(&format!("{base}/")).unwrap_or(base.clone()),
+                    };
+                    if let Ok(candidate) = base.join(rest) {
+                        push_unique(&mut candidates, candidate);
+                    }
+                }
+            }
+            if url.scheme() != MIRROR_SCHEME {
+                push_unique(&mut candidates, url.clone());
+            }
+        }
+        candidates
+    }
+}
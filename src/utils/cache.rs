@@ -0,0 +1,183 @@
+//this is the source cache shared between recipes, downloads are stored by their checksum and git repositories as bare mirrors keyed by url
+use super::{
+    checksum::{is_hex, Algorithm},
+    git,
+    network::Network,
+};
+use crate::progress::Progress;
+use anyhow::{anyhow, Result};
+use git2::Repository;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, copy, create_dir_all, hard_link, remove_dir_all, remove_file, rename},
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// a cache directory, sources are stored under sources/ and git mirrors under git/
+#[derive(Debug, Clone)]
+pub struct Cache {
+    root: PathBuf,
+}
+
+/// the key a git mirror is stored under
+pub fn url_key(url: &Url) -> String {
+    Sha256::digest(url.as_str().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// hard links where possible so a cached tarball only takes up space once
+fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+    if hard_link(from, to).is_err() {
+        copy(from, to)?;
+    }
+    Ok(())
+}
+
+impl Cache {
+    /// the cache kept in root, nothing is created until something is stored
+    pub fn new(root: PathBuf) -> Self {
+        Cache { root }
+    }
+
+    /// $XDG_CACHE_HOME/faebuild, falling back to ~/.cache/faebuild
+    pub fn default_root() -> Result<PathBuf> {
+        if let Some(cache) = env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+            return Ok(PathBuf::from(cache).join("faebuild"));
+        }
+        match env::var_os("HOME") {
+            Some(home) => Ok(PathBuf::from(home).join(".cache").join("faebuild")),
+            None => Err(anyhow!(
+                "neither XDG_CACHE_HOME nor HOME are set, can't find the source cache"
+            )),
+        }
+    }
+
+    fn sources(&self) -> PathBuf {
+        self.root.join("sources")
+    }
+
+    fn mirrors(&self) -> PathBuf {
+        self.root.join("git")
+    }
+
+    /// where the source stored under key is kept. keys come from recipes, so anything but a
+    /// digest, optionally behind the field of its algorithm, is refused rather than joined
+    pub fn source_path(&self, key: &str) -> Result<PathBuf> {
+        let digest = Algorithm::ALL
+            .iter()
+            .find_map(|algorithm| key.strip_prefix(&format!("{}-", algorithm.field())))
+            .unwrap_or(key);
+        if !is_hex(digest) {
+            return Err(anyhow!("{key} is not a valid cache key"));
+        }
+        Ok(self.sources().join(key))
+    }
+
+    /// where the bare mirror of the git repository at url is kept
+    pub fn mirror_path(&self, url: &Url) -> PathBuf {
+        self.mirrors().join(format!("{}.git", url_key(url)))
+    }
+
+    /// places the cached copy of key at out, returns false if it isn't cached
+    pub fn restore(&self, key: &str, out: &Path) -> Result<bool> {
+        let cached = self.source_path(key)?;
+        if !cached.exists() {
+            return Ok(false);
+        }
+        link_or_copy(&cached, out)?;
+        Ok(true)
+    }
+
+    /// adds a verified file to the cache, an entry already stored under key is kept
+    pub fn store(&self, key: &str, file: &Path) -> Result<()> {
+        if self.source_path(key)?.exists() {
+            return Ok(());
+        }
+        self.replace(key, file)
+    }
+
+    /// stores a verified file under key in place of whatever was there, used when the cached
+    /// copy turned out to be corrupted
+    pub fn replace(&self, key: &str, file: &Path) -> Result<()> {
+        let cached = self.source_path(key)?;
+        create_dir_all(self.sources())?;
+        // write under a temporary name so a crash never leaves a truncated entry behind
+        let partial = self.sources().join(format!("{key}.partial"));
+        if partial.exists() {
+            remove_file(&partial)?;
+        }
+        link_or_copy(file, &partial)?;
+        rename(&partial, &cached)?;
+        Ok(())
+    }
+
+    /// opens the bare mirror for url, cloning it first if needed, update fetches from upstream
+    pub fn mirror(
+        &self,
+        url: &Url,
+        update: bool,
+        network: &Network,
+        progress: &dyn Progress,
+    ) -> Result<Repository> {
+        let path = self.mirror_path(url);
+        if path.exists() {
+            let repo = Repository::open_bare(&path)?;
+            if update {
+                git::fetch(&repo, network, progress)?;
+            }
+            return Ok(repo);
+        }
+        if !update {
+            return Err(anyhow!("{url} is not mirrored in {}", path.display()));
+        }
+        create_dir_all(self.mirrors())?;
+        let repo = Repository::init_bare(&path)?;
+        repo.remote_with_fetch("origin", url.as_str(), "+refs/*:refs/*")?;
+        if let Err(e) = git::fetch(&repo, network, progress) {
+            drop(repo);
+            remove_dir_all(&path)?;
+            return Err(anyhow!("Failed to mirror repo: {url}\n{e}"));
+        }
+        Ok(repo)
+    }
+
+    /// removes every entry that isn't in keep_sources or keep_mirrors, returning what was removed
+    pub fn gc(
+        &self,
+        keep_sources: &HashSet<String>,
+        keep_mirrors: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<Vec<PathBuf>> {
+        let mut removed = vec![];
+        for (dir, keep, suffix) in [
+            (self.sources(), keep_sources, ""),
+            (self.mirrors(), keep_mirrors, ".git"),
+        ] {
+            if !dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let key = name.strip_suffix(suffix).unwrap_or(&name);
+                if keep.contains(key) {
+                    continue;
+                }
+                if !dry_run {
+                    if path.is_dir() {
+                        remove_dir_all(&path)?;
+                    } else {
+                        remove_file(&path)?;
+                    }
+                }
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+}
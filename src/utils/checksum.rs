@@ -0,0 +1,138 @@
+//this hashes files with every checksum a source can be pinned with
+use anyhow::{anyhow, Result};
+use blake2::Blake2b512;
+use sha2::{Digest, Sha256, Sha512};
+use std::{fmt, fs::File, io::Read, path::Path};
+
+// files are read in chunks this big, small reads make hashing large tarballs crawl
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// a checksum a source can be pinned with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// sha256sum
+    Sha256,
+    /// sha512sum
+    Sha512,
+    /// b2sum, blake2b with a 512 bit digest
+    Blake2b,
+    /// b3sum
+    Blake3,
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake2b(Blake2b512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Algorithm {
+    /// every algorithm, in the order sums are checked and listed
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::Sha256,
+        Algorithm::Sha512,
+        Algorithm::Blake2b,
+        Algorithm::Blake3,
+    ];
+
+    /// the recipe key a sum of this kind is written under
+    pub fn field(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256sum",
+            Algorithm::Sha512 => "sha512sum",
+            Algorithm::Blake2b => "b2sum",
+            Algorithm::Blake3 => "b3sum",
+        }
+    }
+
+    /// length of the hex encoded digest
+    pub fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 | Algorithm::Blake3 => 64,
+            Algorithm::Sha512 | Algorithm::Blake2b => 128,
+        }
+    }
+
+    /// whether sum is a digest of this kind the way hash_file writes it, lowercase hex of
+    /// exactly hex_len characters
+    pub fn is_sum(self, sum: &str) -> bool {
+        sum.len() == self.hex_len() && is_hex(sum)
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            Algorithm::Blake2b => Hasher::Blake2b(Blake2b512::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::default()),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.field())
+    }
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+            Hasher::Blake2b(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake2b(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// bytes as lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// lowercase hex digits and nothing else
+pub fn is_hex(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// hashes path with each of algorithms in a single read, the sums come back in the same order
+pub fn hash_file(path: &Path, algorithms: &[Algorithm]) -> Result<Vec<String>> {
+    if !path.is_file() {
+        return Err(anyhow!("{} is not a file", path.display()));
+    }
+    let mut file = File::open(path)?;
+    let mut hashers: Vec<Hasher> = algorithms
+        .iter()
+        .map(|algorithm| algorithm.hasher())
+        .collect();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        for hasher in &mut hashers {
+            hasher.update(&buffer[..bytes_read]);
+        }
+    }
+    Ok(hashers
+        .into_iter()
+        .map(|hasher| to_hex(&hasher.finalize()))
+        .collect())
+}
@@ -0,0 +1,426 @@
+//this downloads a url while reporting how far it got, partial downloads are kept next to the output and resumed with a range request over http or a restart offset over ftp
+use super::network::Network;
+use crate::{progress::Progress, userconfig::DownloadSettings};
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    StatusCode,
+};
+use std::{
+    cmp::min,
+    fs::{copy, read_to_string, remove_file, rename, write, File, OpenOptions},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use suppaftp::{tokio::AsyncFtpStream, types::FileType, FtpError};
+use tokio::{
+    io::AsyncReadExt,
+    net::lookup_host,
+    time::{sleep, timeout},
+};
+use url::Url;
+
+/// the url schemes sources can be downloaded from
+pub const SCHEMES: [&str; 4] = ["http", "https", "ftp", "file"];
+
+// whether a failed attempt is worth repeating
+enum Failure {
+    Transient(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+// the sizes a download is held to, expected is the size the recipe gives and max the cap from
+// the user config
+#[derive(Debug, Clone, Copy)]
+struct SizeLimit {
+    expected: Option<u64>,
+    max: Option<u64>,
+}
+
+impl SizeLimit {
+    // fails once size is more than the download may be, a size the server announced up front
+    // also has to match the expected one exactly
+    fn check(&self, url: &Url, size: u64, announced: bool) -> Result<(), Failure> {
+        if let Some(expected) = self.expected {
+            if size > expected || (announced && size != expected) {
+                return Err(Failure::Fatal(anyhow!(
+                    "{url} is {size} bytes{} but the source size is {expected}",
+                    if announced { "" } else { " or more" }
+                )));
+            }
+        }
+        if let Some(max) = self.max.filter(|max| size > *max) {
+            return Err(Failure::Fatal(anyhow!(
+                "{url} is larger than the maximum download size of {max} bytes, raise max_size in the user config if that is expected"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::Fatal(e.into())
+    }
+}
+
+// out with suffix appended to its file name
+fn sibling(out: &Path, suffix: &str) -> PathBuf {
+    let mut name = out.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    out.with_file_name(name)
+}
+
+// connection problems, timeouts and server side errors may go away on their own
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+// ftp replies in the 4xx range are temporary, 5xx ones are not worth repeating
+fn ftp_failure(url: &Url, e: FtpError) -> Failure {
+    let transient = match &e {
+        FtpError::UnexpectedResponse(response) => (400..500).contains(&response.status.code()),
+        FtpError::ConnectionError(_) | FtpError::BadResponse => true,
+        _ => false,
+    };
+    let e = anyhow!("{url}: {e}");
+    match transient {
+        true => Failure::Transient(e),
+        false => Failure::Fatal(e),
+    }
+}
+
+// waits for something from the server, giving up once it has been quiet for the read timeout
+async fn within<F: Future>(
+    settings: &DownloadSettings,
+    url: &Url,
+    wait: F,
+) -> Result<F::Output, Failure> {
+    match DownloadSettings::duration(settings.read_timeout) {
+        Some(limit) => timeout(limit, wait).await.map_err(|_| {
+            Failure::Transient(anyhow!("{url} sent nothing for {}s", settings.read_timeout))
+        }),
+        None => Ok(wait.await),
+    }
+}
+
+// the url and validator a partial download was started with, a resume is only safe when both
+// still match
+fn read_resume_state(state: &Path, url: &Url) -> Option<String> {
+    let state = read_to_string(state).ok()?;
+    let (started_from, validator) = state.split_once('\n')?;
+    if started_from != url.as_str() || validator.is_empty() {
+        return None;
+    }
+    Some(validator.to_owned())
+}
+
+async fn attempt(
+    network: &Network,
+    url: &Url,
+    out: &Path,
+    settings: &DownloadSettings,
+    limit: SizeLimit,
+    progress: &dyn Progress,
+) -> Result<(), Failure> {
+    let part = sibling(out, ".part");
+    let state = sibling(out, ".part.state");
+
+    let mut offset = 0;
+    let mut request = network.get(url);
+    if part.exists() {
+        match read_resume_state(&state, url) {
+            Some(validator) => {
+                offset = part.metadata()?.len();
+                // if the file changed upstream the server answers with all of it instead
+                request = request
+                    .header(RANGE, format!("bytes={offset}-"))
+                    .header(IF_RANGE, validator);
+            }
+            None => remove_file(&part)?,
+        }
+    }
+
+    let res = within(settings, url, request.send())
+        .await?
+        .map_err(|e| Failure::Transient(e.into()))?;
+    let status = res.status();
+    let mut file = match status {
+        StatusCode::PARTIAL_CONTENT => {
+            let expected = format!("bytes {offset}-");
+            let range = res
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .unwrap_or_default();
+            if !range.starts_with(&expected) {
+                remove_file(&part)?;
+                return Err(Failure::Transient(anyhow!(
+                    "{url} answered a request for {expected} with {range}"
+                )));
+            }
+            progress.message(&format!("Resuming {} at {offset} bytes", out.display()));
+            OpenOptions::new().append(true).open(&part)?
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // the partial file is no use to us, start over on the next attempt
+            remove_file(&part)?;
+            return Err(Failure::Transient(anyhow!(
+                "{url} can't resume {} at {offset} bytes",
+                out.display()
+            )));
+        }
+        status if status.is_success() => {
+            offset = 0;
+            let validator = res
+                .headers()
+                .get(ETAG)
+                .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+                .or(res.headers().get(LAST_MODIFIED))
+                .and_then(|validator| validator.to_str().ok())
+                .unwrap_or_default();
+            write(&state, format!("{url}\n{validator}"))?;
+            File::create(&part)
+                .context(format!("Failed to create file '{}'", part.display()))
+                .map_err(Failure::Fatal)?
+        }
+        status => {
+            let e = anyhow!("The {url} return status code {status}");
+            return Err(match is_transient(status) {
+                true => Failure::Transient(e),
+                false => Failure::Fatal(e),
+            });
+        }
+    };
+
+    let announced = res.content_length().map(|length| length + offset);
+    if let Some(total) = announced {
+        if let Err(e) = limit.check(url, total, true) {
+            remove_file(&part)?;
+            return Err(e);
+        }
+    }
+    let total = announced.or(limit.expected);
+    let mut transfer = progress.transfer(url.as_str(), total);
+    transfer.position(offset);
+    let mut downloaded = offset;
+    let mut stream = res.bytes_stream();
+    while let Some(item) = within(settings, url, stream.next()).await? {
+        let chunk = item.map_err(|e| Failure::Transient(e.into()))?;
+        downloaded += chunk.len() as u64;
+        if let Err(e) = limit.check(url, downloaded, false) {
+            remove_file(&part)?;
+            return Err(e);
+        }
+        file.write_all(&chunk)
+            .context("Error while writing to file")
+            .map_err(Failure::Fatal)?;
+        transfer.position(downloaded);
+    }
+
+    if let Some(total) = total.filter(|total| downloaded < *total) {
+        // keep what we got, the next attempt picks up from here
+        return Err(Failure::Transient(anyhow!(
+            "{url} closed the connection after {downloaded} of {total} bytes"
+        )));
+    }
+    rename(&part, out)?;
+    if state.exists() {
+        remove_file(&state)?;
+    }
+    transfer.finish(&format!("Downloaded {} to {}", url, out.display()));
+    Ok(())
+}
+
+// file urls are copied, the caller verifies them like anything downloaded
+fn copy_local(url: &Url, out: &Path, limit: SizeLimit) -> Result<()> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| anyhow!("{url} is not a local path"))?;
+    let size = path
+        .metadata()
+        .context(format!("failed to read {}", path.display()))?
+        .len();
+    if let Err(Failure::Transient(e) | Failure::Fatal(e)) = limit.check(url, size, true) {
+        return Err(e);
+    }
+    let part = sibling(out, ".part");
+    copy(&path, &part).context(format!("failed to copy {}", path.display()))?;
+    rename(&part, out)?;
+    Ok(())
+}
+
+// fetches url over ftp in passive mode. there are no etags, the size and modification time the
+// server reports stand in for one so a partial file is only resumed if it is still the same file
+async fn attempt_ftp(
+    network: &Network,
+    url: &Url,
+    out: &Path,
+    settings: &DownloadSettings,
+    limit: SizeLimit,
+    progress: &dyn Progress,
+) -> Result<(), Failure> {
+    let part = sibling(out, ".part");
+    let state = sibling(out, ".part.state");
+    let host = url
+        .host_str()
+        .ok_or_else(|| Failure::Fatal(anyhow!("{url} has no host")))?;
+    let path = percent_decode_str(url.path())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    let address = lookup_host((host, url.port().unwrap_or(21)))
+        .await
+        .map_err(|e| Failure::Transient(anyhow!("{url}: {e}")))?
+        .next()
+        .ok_or_else(|| Failure::Fatal(anyhow!("{url}: {host} has no address")))?;
+    let connect = match DownloadSettings::duration(settings.connect_timeout) {
+        Some(limit) => AsyncFtpStream::connect_timeout(address, limit).await,
+        None => AsyncFtpStream::connect(address).await,
+    };
+    let mut ftp = connect.map_err(|e| ftp_failure(url, e))?;
+    let (user, password) = network.ftp_login(url);
+    within(settings, url, ftp.login(user, password))
+        .await?
+        .map_err(|e| ftp_failure(url, e))?;
+    within(settings, url, ftp.transfer_type(FileType::Binary))
+        .await?
+        .map_err(|e| ftp_failure(url, e))?;
+
+    // not every server implements these, without them a download can't be resumed
+    let size = within(settings, url, ftp.size(&path)).await?.ok();
+    let modified = within(settings, url, ftp.mdtm(&path)).await?.ok();
+    let size = size.map(|size| size as u64);
+    if let Some(size) = size {
+        limit.check(url, size, true)?;
+    }
+    let validator = match (size, modified) {
+        (Some(size), Some(modified)) => format!("{size} {modified}"),
+        _ => String::new(),
+    };
+
+    let mut offset = 0;
+    if part.exists() {
+        match read_resume_state(&state, url).filter(|previous| *previous == validator) {
+            Some(_) => offset = part.metadata()?.len(),
+            None => remove_file(&part)?,
+        }
+    }
+    let mut file = if offset > 0 {
+        within(settings, url, ftp.resume_transfer(offset as usize))
+            .await?
+            .map_err(|e| ftp_failure(url, e))?;
+        progress.message(&format!("Resuming {} at {offset} bytes", out.display()));
+        OpenOptions::new().append(true).open(&part)?
+    } else {
+        write(&state, format!("{url}\n{validator}"))?;
+        File::create(&part)
+            .context(format!("Failed to create file '{}'", part.display()))
+            .map_err(Failure::Fatal)?
+    };
+
+    let mut stream = within(settings, url, ftp.retr_as_stream(&path))
+        .await?
+        .map_err(|e| ftp_failure(url, e))?;
+    let mut transfer = progress.transfer(url.as_str(), size.or(limit.expected));
+    transfer.position(offset);
+    let mut downloaded = offset;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = within(settings, url, stream.read(&mut buffer))
+            .await?
+            .map_err(|e| Failure::Transient(e.into()))?;
+        if read == 0 {
+            break;
+        }
+        if let Err(e) = limit.check(url, downloaded + read as u64, false) {
+            remove_file(&part)?;
+            return Err(e);
+        }
+        file.write_all(&buffer[..read])
+            .context("Error while writing to file")
+            .map_err(Failure::Fatal)?;
+        downloaded += read as u64;
+        transfer.position(downloaded);
+    }
+    within(settings, url, stream.finish())
+        .await?
+        .map_err(|e| ftp_failure(url, e))?;
+    // the transfer is complete, a failing quit doesn't change that
+    let _ = within(settings, url, ftp.quit()).await;
+
+    if let Some(total) = size.or(limit.expected).filter(|total| downloaded < *total) {
+        return Err(Failure::Transient(anyhow!(
+            "{url} closed the connection after {downloaded} of {total} bytes"
+        )));
+    }
+    rename(&part, out)?;
+    if state.exists() {
+        remove_file(&state)?;
+    }
+    transfer.finish(&format!("Downloaded {} to {}", url, out.display()));
+    Ok(())
+}
+
+/// downloads url to out, retrying transient failures with exponential backoff and resuming
+/// whatever the earlier attempts got. size is what the download is expected to be if it is
+/// known. how far it got is reported to progress
+pub async fn download_with_pb(
+    url: Url,
+    out: &Path,
+    size: Option<u64>,
+    network: &Network,
+    settings: &DownloadSettings,
+    progress: &dyn Progress,
+) -> Result<()> {
+    let limit = SizeLimit {
+        expected: size,
+        max: (settings.max_size > 0).then_some(settings.max_size),
+    };
+    match url.scheme() {
+        "file" => return copy_local(&url, out, limit),
+        "http" | "https" | "ftp" => {}
+        scheme => {
+            return Err(anyhow!(
+                "{url} uses {scheme}://, which can't be downloaded from, use one of {}",
+                SCHEMES.join(", ")
+            ))
+        }
+    }
+    let attempts = settings.attempts.max(1);
+    let mut delay = settings.backoff;
+    let mut tried = 1;
+    loop {
+        let attempted = async {
+            match url.scheme() {
+                "ftp" => attempt_ftp(network, &url, out, settings, limit, progress).await,
+                _ => attempt(network, &url, out, settings, limit, progress).await,
+            }
+        };
+        let result = match DownloadSettings::duration(settings.timeout) {
+            Some(limit) => timeout(limit, attempted).await.unwrap_or_else(|_| {
+                Err(Failure::Transient(anyhow!(
+                    "{url} took longer than {}s",
+                    settings.timeout
+                )))
+            }),
+            None => attempted.await,
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(Failure::Transient(e)) if tried < attempts => {
+                progress.warning(&format!("{e}, retrying in {delay}s ({tried}/{attempts})"));
+                sleep(Duration::from_secs(delay)).await;
+                delay = min(delay * 2, settings.max_backoff);
+                tried += 1;
+            }
+            Err(Failure::Transient(e) | Failure::Fatal(e)) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//this unpacks tar, zip and 7z archive sources along with single compressed files, archive entries can have leading directories stripped and be picked out by glob before the guard lets them be written
+use super::{
+    format::{decoder, ArchiveFormat, Compression},
+    guard::{Guard, Kind},
+};
+use crate::{error::Error, userconfig::ExtractSettings};
+use anyhow::{anyhow, Context, Result};
+use glob::Pattern;
+use sevenz_rust::{Password, SevenZArchiveEntry, SevenZReader};
+use std::{
+    fs::{create_dir_all, File},
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+};
+use tar::{Archive, EntryType};
+use zip::ZipArchive;
+
+// longer symlink targets than the kernel takes aren't read
+const LINK_MAX: u64 = 4096;
+
+/// which entries of an archive are unpacked and where below the destination they end up
+#[derive(Debug, Default)]
+pub struct ArchiveFilter {
+    strip_components: usize,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+fn patterns(globs: &[String]) -> Result<Vec<Pattern>> {
+    globs
+        .iter()
+        .map(|glob| {
+            Pattern::new(glob.trim_start_matches('/'))
+                .context(format!("{glob} is not a valid glob"))
+        })
+        .collect()
+}
+
+impl ArchiveFilter {
+    /// a filter dropping strip_components leading components from every entry and keeping the
+    /// entries that match include but not exclude
+    pub fn new(strip_components: usize, include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(ArchiveFilter {
+            strip_components,
+            include: patterns(include)?,
+            exclude: patterns(exclude)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.strip_components == 0 && self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    // the path an entry is unpacked to relative to the destination, None when it is skipped.
+    // globs are matched after stripping, an entry is also picked when one of its parent
+    // directories matches
+    fn target(&self, path: &Path) -> Result<Option<PathBuf>> {
+        let mut relative = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                _ => {
+                    return Err(anyhow!(
+                        "archive entry {} points outside the archive",
+                        path.display()
+                    ))
+                }
+            }
+        }
+        let stripped: PathBuf = relative.components().skip(self.strip_components).collect();
+        if stripped.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        let matches = |patterns: &[Pattern]| {
+            stripped
+                .ancestors()
+                .filter(|path| !path.as_os_str().is_empty())
+                .any(|path| patterns.iter().any(|pattern| pattern.matches_path(path)))
+        };
+        if (!self.include.is_empty() && !matches(&self.include)) || matches(&self.exclude) {
+            return Ok(None);
+        }
+        Ok(Some(stripped))
+    }
+}
+
+fn tar_kind(entry_type: EntryType) -> Kind {
+    if entry_type.is_dir() {
+        Kind::Directory
+    } else if entry_type.is_symlink() {
+        Kind::Symlink
+    } else if entry_type.is_hard_link() {
+        Kind::HardLink
+    } else if entry_type.is_character_special() || entry_type.is_block_special() {
+        Kind::Device
+    } else if entry_type.is_fifo() {
+        Kind::Fifo
+    } else {
+        Kind::File
+    }
+}
+
+// directories are unpacked last so read only ones don't stop their contents from being written
+pub(super) fn unpack_tar(
+    reader: impl Read,
+    guard: &mut Guard,
+    filter: &ArchiveFilter,
+) -> Result<()> {
+    let mut archive = Archive::new(reader);
+    let mut directories = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        guard.count(&path)?;
+        let Some(relative) = filter.target(&path)? else {
+            continue;
+        };
+        let kind = tar_kind(entry.header().entry_type());
+        let out = guard.place(&path, &relative, kind, entry.header().mode().ok())?;
+        match kind {
+            Kind::Directory => directories.push((entry, path, out)),
+            Kind::Symlink => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| anyhow!("symlink {} has no target", path.display()))?;
+                guard.symlink(&path, &target, &out)?;
+            }
+            Kind::HardLink => {
+                // hard links name an earlier entry of the archive, which carries the data and
+                // has been moved the same way
+                let link = entry
+                    .link_name()?
+                    .ok_or_else(|| anyhow!("hard link {} has no target", path.display()))?;
+                let source = filter
+                    .target(&link)
+                    .map_err(|_| {
+                        anyhow!(
+                            "{} is a hard link to {}, which is outside the archive",
+                            path.display(),
+                            link.display()
+                        )
+                    })?
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "hard link {} points to {}, which isn't extracted, include it as well",
+                            path.display(),
+                            link.display()
+                        )
+                    })?;
+                guard.hard_link(&path, &source, &out)?;
+            }
+            _ => {
+                // tar writes exactly the size in the header, so it can be counted up front
+                guard.reserve(&path, entry.size())?;
+                entry
+                    .unpack(&out)
+                    .context(format!("failed to extract {}", path.display()))?;
+            }
+        }
+    }
+    for (mut entry, path, out) in directories.into_iter().rev() {
+        guard.directory(&path, &out)?;
+        entry
+            .unpack(&out)
+            .context(format!("failed to extract {}", path.display()))?;
+    }
+    Ok(())
+}
+
+// the target of a zip or 7z symlink is stored as its contents
+fn read_link_target(reader: &mut dyn Read) -> Result<PathBuf> {
+    let mut target = String::new();
+    reader.take(LINK_MAX).read_to_string(&mut target)?;
+    Ok(PathBuf::from(target))
+}
+
+// unpacks one zip or 7z entry, the directories are returned to have their modes set at the end
+fn unpack_entry(
+    guard: &mut Guard,
+    filter: &ArchiveFilter,
+    path: &Path,
+    is_dir: bool,
+    mode: Option<u32>,
+    reader: &mut dyn Read,
+) -> Result<Option<(PathBuf, PathBuf, Option<u32>)>> {
+    guard.count(path)?;
+    let Some(relative) = filter.target(path)? else {
+        return Ok(None);
+    };
+    let kind = Kind::from_mode(mode, is_dir);
+    let out = guard.place(path, &relative, kind, mode)?;
+    match kind {
+        Kind::Directory => {
+            create_dir_all(&out)?;
+            return Ok(Some((path.to_owned(), out, mode)));
+        }
+        Kind::Symlink => {
+            let target = read_link_target(reader)?;
+            guard.symlink(path, &target, &out)?;
+        }
+        _ => guard.write(path, reader, &out, mode)?,
+    }
+    Ok(None)
+}
+
+fn unpack_zip(file: File, guard: &mut Guard, filter: &ArchiveFilter) -> Result<()> {
+    let mut archive = ZipArchive::new(file)?;
+    let mut directories = vec![];
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let path = entry
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("archive entry {} points outside the archive", entry.name()))?
+            .to_owned();
+        let (is_dir, mode) = (entry.is_dir(), entry.unix_mode());
+        directories.extend(unpack_entry(
+            guard, filter, &path, is_dir, mode, &mut entry,
+        )?);
+    }
+    for (path, out, mode) in directories.into_iter().rev() {
+        guard.set_directory_mode(&path, &out, mode)?;
+    }
+    Ok(())
+}
+
+// 7z keeps unix modes in the upper half of the windows attributes when this bit is set
+const SEVENZ_UNIX_EXTENSION: u32 = 0x8000;
+
+fn unpack_7z(src_out: &Path, guard: &mut Guard, filter: &ArchiveFilter) -> Result<()> {
+    let mut archive = SevenZReader::open(src_out, Password::empty())?;
+    let mut directories = vec![];
+    let mut failure = None;
+    let mut unpack = |entry: &SevenZArchiveEntry, reader: &mut dyn Read| -> Result<()> {
+        let path = PathBuf::from(entry.name());
+        let attributes = entry.windows_attributes();
+        let mode = (entry.has_windows_attributes && attributes & SEVENZ_UNIX_EXTENSION != 0)
+            .then_some(attributes >> 16);
+        directories.extend(unpack_entry(
+            guard,
+            filter,
+            &path,
+            entry.is_directory(),
+            mode,
+            reader,
+        )?);
+        // solid archives share one stream, whatever an entry didn't use has to be read past
+        io::copy(reader, &mut io::sink())?;
+        Ok(())
+    };
+    archive.for_each_entries(|entry, reader| match unpack(entry, reader) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            failure = Some(e);
+            Ok(false)
+        }
+    })?;
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    for (path, out, mode) in directories.into_iter().rev() {
+        guard.set_directory_mode(&path, &out, mode)?;
+    }
+    Ok(())
+}
+
+// a single compressed file is written out under its name without the compression extension
+fn unpack_compressed(
+    src_out: &Path,
+    guard: &mut Guard,
+    compression: Compression,
+    filter: &ArchiveFilter,
+) -> Result<()> {
+    if !filter.is_empty() {
+        return Err(anyhow!(
+            "{} is a single compressed file, strip_components, include and exclude only apply to archives",
+            src_out.display()
+        ));
+    }
+    let name = src_out
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match ArchiveFormat::from_name(&name) {
+        Some((ArchiveFormat::Compressed(_), stem)) if !stem.is_empty() => stem.to_owned(),
+        _ => name,
+    };
+    let path = Path::new(&name);
+    guard.count(path)?;
+    let out = guard.place(path, path, Kind::File, None)?;
+    let mut reader = decoder(compression, BufReader::new(File::open(src_out)?))?;
+    guard.write(path, &mut reader, &out, None)
+}
+
+/// unpacks src_out into dest, the caller has already verified its checksum but not that it is
+/// safe to unpack, which the guard takes care of. the format is sniffed from the file unless
+/// the source names one
+pub async fn extract_archive(
+    src_out: &Path,
+    dest: &Path,
+    format: Option<ArchiveFormat>,
+    filter: &ArchiveFilter,
+    settings: &ExtractSettings,
+) -> crate::error::Result<PathBuf> {
+    unpack(src_out, dest, format, filter, settings).map_err(|cause| Error::Extract {
+        archive: src_out.to_owned(),
+        cause,
+    })?;
+    Ok(dest.to_owned())
+}
+
+fn unpack(
+    src_out: &Path,
+    dest: &Path,
+    format: Option<ArchiveFormat>,
+    filter: &ArchiveFilter,
+    settings: &ExtractSettings,
+) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => ArchiveFormat::detect(src_out)?,
+    };
+    create_dir_all(dest)?;
+    let mut guard = Guard::new(dest, settings)?;
+    match format {
+        ArchiveFormat::Tar(compression) => {
+            let reader = decoder(compression, BufReader::new(File::open(src_out)?))?;
+            unpack_tar(reader, &mut guard, filter)?
+        }
+        ArchiveFormat::Zip => unpack_zip(File::open(src_out)?, &mut guard, filter)?,
+        ArchiveFormat::SevenZip => unpack_7z(src_out, &mut guard, filter)?,
+        ArchiveFormat::Compressed(compression) => {
+            unpack_compressed(src_out, &mut guard, compression, filter)?
+        }
+    }
+    guard.finish()
+}
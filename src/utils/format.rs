@@ -0,0 +1,451 @@
+//this works out what kind of archive a source is from its first bytes, falling back to its file name, and opens the decompressor for it
+use anyhow::{anyhow, Context, Result};
+use bzip2::read::MultiBzDecoder;
+use flate2::{read::MultiGzDecoder, Crc};
+use lzma_rust::LZMAReader;
+use serde::Deserialize;
+use std::{
+    array,
+    cell::RefCell,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind, Read},
+    path::Path,
+    rc::Rc,
+    str::FromStr,
+};
+use xz2::read::XzDecoder;
+use zstd::stream::Decoder;
+
+// tar headers are one block, the magic sits at the same offset in every one of them
+const TAR_BLOCK: usize = 512;
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+// lzma picks its dictionary size from the file, this keeps a hostile header from asking for
+// gigabytes, lzip itself never uses more than 512MiB
+const LZMA_MEM_LIMIT_KB: u32 = 1024 * 1024;
+const LZIP_MAGIC: &[u8] = b"LZIP";
+const LZIP_HEADER: usize = 6;
+const LZIP_TRAILER: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Lzip,
+    Lzma,
+}
+
+/// what an archive source is, written in recipes as its usual extension such as tar.gz or zip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ArchiveFormat {
+    /// a tar, compressed or not
+    Tar(Compression),
+    /// a zip
+    Zip,
+    /// a 7z
+    SevenZip,
+    /// a single compressed file rather than an archive, it is unpacked under its own name
+    /// without the compression extension
+    Compressed(Compression),
+}
+
+// every name a format goes by, used both for the format field and for file extensions. longer
+// names come first so foo.tar.gz is never taken for a plain .gz
+const NAMES: &[(&str, ArchiveFormat)] = &[
+    ("tar.gz", ArchiveFormat::Tar(Compression::Gzip)),
+    ("tar.bz2", ArchiveFormat::Tar(Compression::Bzip2)),
+    ("tar.xz", ArchiveFormat::Tar(Compression::Xz)),
+    ("tar.zst", ArchiveFormat::Tar(Compression::Zstd)),
+    ("tar.zstd", ArchiveFormat::Tar(Compression::Zstd)),
+    ("tar.lz", ArchiveFormat::Tar(Compression::Lzip)),
+    ("tar.lzma", ArchiveFormat::Tar(Compression::Lzma)),
+    ("tgz", ArchiveFormat::Tar(Compression::Gzip)),
+    ("tbz", ArchiveFormat::Tar(Compression::Bzip2)),
+    ("tbz2", ArchiveFormat::Tar(Compression::Bzip2)),
+    ("txz", ArchiveFormat::Tar(Compression::Xz)),
+    ("tzst", ArchiveFormat::Tar(Compression::Zstd)),
+    ("tar", ArchiveFormat::Tar(Compression::None)),
+    ("zip", ArchiveFormat::Zip),
+    ("7z", ArchiveFormat::SevenZip),
+    ("gz", ArchiveFormat::Compressed(Compression::Gzip)),
+    ("bz2", ArchiveFormat::Compressed(Compression::Bzip2)),
+    ("xz", ArchiveFormat::Compressed(Compression::Xz)),
+    ("zst", ArchiveFormat::Compressed(Compression::Zstd)),
+    ("zstd", ArchiveFormat::Compressed(Compression::Zstd)),
+    ("lz", ArchiveFormat::Compressed(Compression::Lzip)),
+    ("lzma", ArchiveFormat::Compressed(Compression::Lzma)),
+];
+
+impl FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, format)| *format)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{name} is not an archive format, use one of {}",
+                    NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+impl TryFrom<String> for ArchiveFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(name: String) -> Result<Self> {
+        name.parse()
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = NAMES
+            .iter()
+            .find(|(_, format)| format == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default();
+        write!(f, "{name}")
+    }
+}
+
+impl ArchiveFormat {
+    /// the format a file name ends in, along with the name without that ending
+    pub fn from_name(name: &str) -> Option<(ArchiveFormat, &str)> {
+        let lowercase = name.to_ascii_lowercase();
+        NAMES.iter().find_map(|(extension, format)| {
+            let stem = lowercase.strip_suffix(extension)?.strip_suffix('.')?;
+            Some((*format, &name[..stem.len()]))
+        })
+    }
+
+    /// sniffs the format from the first bytes of path, a compressed stream is a tar when the
+    /// decompressed data starts with a tar header. old tars without the ustar magic are only
+    /// recognised by their name
+    pub fn detect(path: &Path) -> Result<ArchiveFormat> {
+        let mut header = [0; TAR_BLOCK];
+        let read = read_full(&mut File::open(path)?, &mut header)?;
+        let header = &header[..read];
+        let by_name = path.file_name().and_then(|name| {
+            ArchiveFormat::from_name(&name.to_string_lossy()).map(|(format, _)| format)
+        });
+
+        let compression = if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06")
+        {
+            return Ok(ArchiveFormat::Zip);
+        } else if header.starts_with(b"7z\xbc\xaf\x27\x1c") {
+            return Ok(ArchiveFormat::SevenZip);
+        } else if is_tar(header) {
+            return Ok(ArchiveFormat::Tar(Compression::None));
+        } else if header.starts_with(b"\x1f\x8b") {
+            Compression::Gzip
+        } else if header.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if header.starts_with(b"\xfd7zXZ\x00") {
+            Compression::Xz
+        } else if header.starts_with(b"\x28\xb5\x2f\xfd") {
+            Compression::Zstd
+        } else if header.starts_with(LZIP_MAGIC) {
+            Compression::Lzip
+        } else if header.starts_with(b"\x5d\x00\x00") {
+            // lzma has no magic, this is its usual properties byte followed by a dictionary
+            // size that is a multiple of 64KiB
+            Compression::Lzma
+        } else {
+            return by_name.ok_or_else(|| {
+                anyhow!(
+                    "can't tell what kind of archive {} is, set format on the source",
+                    path.display()
+                )
+            });
+        };
+
+        let mut block = [0; TAR_BLOCK];
+        let mut decoder = decoder(compression, BufReader::new(File::open(path)?))?;
+        let read = read_full(&mut decoder, &mut block)
+            .context(format!("failed to decompress {}", path.display()))?;
+        if is_tar(&block[..read]) || by_name == Some(ArchiveFormat::Tar(compression)) {
+            Ok(ArchiveFormat::Tar(compression))
+        } else {
+            Ok(ArchiveFormat::Compressed(compression))
+        }
+    }
+}
+
+fn is_tar(block: &[u8]) -> bool {
+    block.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+}
+
+// fills buf as far as the reader goes, returning how much it got
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// the decompressed contents of reader, concatenated streams are read as one like the command
+/// line tools do
+pub fn decoder<R: BufRead + 'static>(compression: Compression, reader: R) -> Result<Box<dyn Read>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Compression::Zstd => Box::new(Decoder::with_buffer(reader)?),
+        Compression::Lzip => Box::new(LzipReader::new(reader)?),
+        Compression::Lzma => Box::new(LZMAReader::new_mem_limit(reader, LZMA_MEM_LIMIT_KB, None)?),
+    })
+}
+
+// hands the same reader to each lzma stream in turn, so the lzip trailers in between them can
+// be read too
+struct Shared<R>(Rc<RefCell<R>>);
+
+impl<R: Read> Read for Shared<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+// an lzip file is one or more members, each a header, a raw lzma stream with fixed properties
+// and a trailer holding the crc and size of the data
+struct LzipReader<R> {
+    inner: Rc<RefCell<R>>,
+    member: Option<LZMAReader<Shared<R>>>,
+    crc: Crc,
+    size: u64,
+}
+
+impl<R: Read> LzipReader<R> {
+    fn new(reader: R) -> io::Result<Self> {
+        let mut lzip = LzipReader {
+            inner: Rc::new(RefCell::new(reader)),
+            member: None,
+            crc: Crc::new(),
+            size: 0,
+        };
+        lzip.member = lzip.next_member()?;
+        if lzip.member.is_none() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "empty lzip file"));
+        }
+        Ok(lzip)
+    }
+
+    fn next_member(&mut self) -> io::Result<Option<LZMAReader<Shared<R>>>> {
+        let mut header = [0; LZIP_HEADER];
+        match read_full(&mut *self.inner.borrow_mut(), &mut header)? {
+            0 => return Ok(None),
+            LZIP_HEADER if header.starts_with(LZIP_MAGIC) && header[4] == 1 => {}
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "not an lzip member")),
+        }
+        let base = 1u32 << (header[5] & 0x1f);
+        let dict_size = base - (base / 16) * u32::from(header[5] >> 5);
+        if !(1 << 12..=1 << 29).contains(&dict_size) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid lzip dictionary size",
+            ));
+        }
+        self.crc = Crc::new();
+        self.size = 0;
+        let shared = Shared(self.inner.clone());
+        LZMAReader::new(shared, u64::MAX, 3, 0, 2, dict_size, None).map(Some)
+    }
+
+    fn check_trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0; LZIP_TRAILER];
+        if read_full(&mut *self.inner.borrow_mut(), &mut trailer)? != LZIP_TRAILER {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "lzip member is cut short",
+            ));
+        }
+        let crc = u32::from_le_bytes(trailer_field(&trailer, 0));
+        let size = u64::from_le_bytes(trailer_field(&trailer, 4));
+        if crc != self.crc.sum() || size != self.size {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "lzip member doesn't match its crc",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// N bytes of an lzip trailer starting at offset, the crc is at 0 and the data size at 4
+fn trailer_field<const N: usize>(trailer: &[u8; LZIP_TRAILER], offset: usize) -> [u8; N] {
+    array::from_fn(|i| trailer[offset + i])
+}
+
+impl<R: Read> Read for LzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(member) = &mut self.member {
+            let read = member.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.crc.update(&buf[..read]);
+                self.size += read as u64;
+                return Ok(read);
+            }
+            self.check_trailer()?;
+            self.member = self.next_member()?;
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use xz2::{
+        read::XzEncoder,
+        stream::{LzmaOptions, Stream},
+    };
+
+    const DICT_SIZE: u32 = 1 << 16;
+
+    // data as an .lzma file, a 13 byte header followed by an lzma stream with an end marker
+    fn lzma(data: &[u8]) -> Vec<u8> {
+        let mut options = LzmaOptions::new_preset(6).unwrap();
+        options.dict_size(DICT_SIZE);
+        let stream = Stream::new_lzma_encoder(&options).unwrap();
+        let mut compressed = vec![];
+        XzEncoder::new_stream(data, stream)
+            .read_to_end(&mut compressed)
+            .unwrap();
+        compressed
+    }
+
+    // one lzip member per part, the lzma stream is the same as in an .lzma file without its header
+    fn lzip(parts: &[&[u8]]) -> Vec<u8> {
+        let mut file = vec![];
+        for part in parts {
+            let start = file.len();
+            file.extend_from_slice(LZIP_MAGIC);
+            file.extend_from_slice(&[1, DICT_SIZE.trailing_zeros() as u8]);
+            file.extend_from_slice(&lzma(part)[13..]);
+            let mut crc = Crc::new();
+            crc.update(part);
+            file.extend_from_slice(&crc.sum().to_le_bytes());
+            file.extend_from_slice(&(part.len() as u64).to_le_bytes());
+            let member = (file.len() - start + 8) as u64;
+            file.extend_from_slice(&member.to_le_bytes());
+        }
+        file
+    }
+
+    fn decompress(compression: Compression, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        decoder(compression, io::Cursor::new(data))
+            .map_err(io::Error::other)?
+            .read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn detect(name: &str, data: &[u8]) -> ArchiveFormat {
+        let dir = env::temp_dir().join(format!("faebuild-format-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, data).unwrap();
+        let format = ArchiveFormat::detect(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_dir(&dir);
+        format
+    }
+
+    fn tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_ustar();
+        header.set_size(5);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "a", &b"hello"[..])
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn reads_every_lzip_member() {
+        let data = lzip(&[b"hello ", b"", b"world"]);
+        assert_eq!(decompress(Compression::Lzip, data).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn refuses_a_truncated_lzip_member() {
+        let mut data = lzip(&[b"hello ", b"world"]);
+        // into the trailer of the last member, then into its lzma stream
+        data.truncate(data.len() - 4);
+        assert!(decompress(Compression::Lzip, data.clone()).is_err());
+        data.truncate(data.len() - 20);
+        assert!(decompress(Compression::Lzip, data).is_err());
+    }
+
+    #[test]
+    fn refuses_a_bad_lzip_crc() {
+        let mut data = lzip(&[b"hello ", b"world"]);
+        let trailer = data.len() - LZIP_TRAILER;
+        data[trailer] ^= 0xff;
+        let error = decompress(Compression::Lzip, data).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("crc"), "{error}");
+
+        let mut data = lzip(&[b"hello ", b"world"]);
+        data[trailer + 4] ^= 0xff;
+        assert!(decompress(Compression::Lzip, data).is_err());
+    }
+
+    #[test]
+    fn refuses_an_empty_lzip_file() {
+        assert!(decompress(Compression::Lzip, vec![]).is_err());
+        assert!(decompress(Compression::Lzip, b"LZIP".to_vec()).is_err());
+    }
+
+    #[test]
+    fn reads_lzma() {
+        assert_eq!(
+            decompress(Compression::Lzma, lzma(b"hello world")).unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[test]
+    fn sniffs_lzip_and_lzma() {
+        let tar = tar();
+        // names that say nothing so only the bytes count
+        assert_eq!(
+            detect("lzip-tar", &lzip(&[&tar])),
+            ArchiveFormat::Tar(Compression::Lzip)
+        );
+        assert_eq!(
+            detect("lzip-file", &lzip(&[b"hello"])),
+            ArchiveFormat::Compressed(Compression::Lzip)
+        );
+        assert_eq!(
+            detect("lzma-tar", &lzma(&tar)),
+            ArchiveFormat::Tar(Compression::Lzma)
+        );
+        assert_eq!(
+            detect("lzma-file", &lzma(b"hello")),
+            ArchiveFormat::Compressed(Compression::Lzma)
+        );
+    }
+}
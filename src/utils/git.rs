@@ -1,20 +1,56 @@
+use super::network::Network;
+use crate::progress::Progress;
 use anyhow::Result;
-use git2::{AutotagOption, FetchOptions, RemoteCallbacks, Repository};
-use std::{
-    io::{self, Write},
-    str,
+use git2::{
+    build::RepoBuilder, AutotagOption, RemoteCallbacks, Repository, SubmoduleUpdateOptions,
 };
+use std::path::Path;
 
-pub fn fetch(repo: &Repository) -> Result<()> {
-    let mut cb = RemoteCallbacks::new();
+/// clones url into out through network, with recursive its submodules are cloned the same way
+pub fn clone(url: &str, out: &Path, recursive: bool, network: &Network) -> Result<Repository> {
+    let repo = RepoBuilder::new()
+        .fetch_options(network.git_fetch_options(url, RemoteCallbacks::new()))
+        .clone(url, out)?;
+    if recursive {
+        update_submodules(&repo, network)?;
+    }
+    Ok(repo)
+}
+
+fn update_submodules(repo: &Repository, network: &Network) -> Result<()> {
+    for mut submodule in repo.submodules()? {
+        let url = submodule.url().unwrap_or_default().to_owned();
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(network.git_fetch_options(&url, RemoteCallbacks::new()));
+        submodule.update(true, Some(&mut options))?;
+        update_submodules(&submodule.open()?, network)?;
+    }
+    Ok(())
+}
 
+/// fetches every branch and tag of the origin remote of repo, or of an anonymous one when it has
+/// no origin
+pub fn fetch(repo: &Repository, network: &Network, progress: &dyn Progress) -> Result<()> {
     let mut remote = repo
         .find_remote("origin")
         .or_else(|_| repo.remote_anonymous("origin"))?;
+    let url = remote.url().unwrap_or_default().to_owned();
+    let mut transfer = progress.transfer(&url, None);
 
+    let mut cb = RemoteCallbacks::new();
+
+    // the remote redraws its counters with \r, only the last state of each line is passed on
     cb.sideband_progress(|data| {
-        print!("remote: {}", str::from_utf8(data).unwrap());
-        io::stdout().flush().unwrap();
+        let text = String::from_utf8_lossy(data);
+        for line in text
+            .split_inclusive('\n')
+            .filter(|line| line.ends_with('\n'))
+        {
+            let line = line.trim_end().rsplit('\r').next().unwrap_or_default();
+            if !line.is_empty() {
+                progress.message(&format!("remote: {line}"));
+            }
+        }
         true
     });
 
@@ -23,63 +59,44 @@ pub fn fetch(repo: &Repository) -> Result<()> {
     // update.
     cb.update_tips(|refname, a, b| {
         if a.is_zero() {
-            println!("[new]     {:20} {}", b, refname);
+            progress.message(&format!("[new]     {:20} {}", b, refname));
         } else {
-            println!("[updated] {:10}..{:10} {}", a, b, refname);
+            progress.message(&format!("[updated] {:10}..{:10} {}", a, b, refname));
         }
         true
     });
 
-    // Here we show processed and total objects in the pack and the amount of
-    // received data. Most frontends will probably want to show a percentage and
-    // the download rate.
     cb.transfer_progress(|stats| {
-        if stats.received_objects() == stats.total_objects() {
-            print!(
-                "Resolving deltas {}/{}\r",
-                stats.indexed_deltas(),
-                stats.total_deltas()
-            );
-        } else if stats.total_objects() > 0 {
-            print!(
-                "Received {}/{} objects ({}) in {} bytes\r",
-                stats.received_objects(),
-                stats.total_objects(),
-                stats.indexed_objects(),
-                stats.received_bytes()
-            );
-        }
-        io::stdout().flush().unwrap();
+        transfer.position(stats.received_bytes() as u64);
         true
     });
 
     // Download the packfile and index it. This function updates the amount of
     // received data and the indexer stats which lets you inform the user about
     // progress.
-    let mut fo = FetchOptions::new();
-    fo.remote_callbacks(cb);
+    let mut fo = network.git_fetch_options(&url, cb);
     remote.download(&[] as &[&str], Some(&mut fo))?;
+    drop(fo);
 
     {
         // If there are local objects (we got a thin pack), then tell the user
         // how many objects we saved from having to cross the network.
         let stats = remote.stats();
         if stats.local_objects() > 0 {
-            println!(
-                "\rReceived {}/{} objects in {} bytes (used {} local \
-                 objects)",
+            transfer.finish(&format!(
+                "Received {}/{} objects in {} bytes (used {} local objects)",
                 stats.indexed_objects(),
                 stats.total_objects(),
                 stats.received_bytes(),
                 stats.local_objects()
-            );
+            ));
         } else {
-            println!(
-                "\rReceived {}/{} objects in {} bytes",
+            transfer.finish(&format!(
+                "Received {}/{} objects in {} bytes",
                 stats.indexed_objects(),
                 stats.total_objects(),
                 stats.received_bytes()
-            );
+            ));
         }
     }
 
@@ -0,0 +1,549 @@
+//this keeps untrusted archives inside the directory they are unpacked into and within the unpack limits, every entry is checked here before anything is written for it
+use crate::userconfig::ExtractSettings;
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs::{self, create_dir_all, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
+// the same limit the kernel puts on following symlinks
+const MAX_LINK_DEPTH: u32 = 40;
+const SETID: u32 = 0o6000;
+// permission bits kept on unpacked files, setuid and setgid entries are refused and the sticky
+// bit means nothing in a build tree
+const PERMISSIONS: u32 = 0o777;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFIFO: u32 = 0o010000;
+const S_IFSOCK: u32 = 0o140000;
+
+// what an archive entry turns into once unpacked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    Device,
+    Fifo,
+}
+
+impl Kind {
+    // the kind of a zip or 7z entry from the unix mode stored with it
+    pub fn from_mode(mode: Option<u32>, is_dir: bool) -> Kind {
+        if is_dir {
+            return Kind::Directory;
+        }
+        match mode.unwrap_or_default() & S_IFMT {
+            S_IFLNK => Kind::Symlink,
+            S_IFCHR | S_IFBLK => Kind::Device,
+            S_IFIFO | S_IFSOCK => Kind::Fifo,
+            _ => Kind::File,
+        }
+    }
+}
+
+// where path really leads, following every symlink along it. unlike canonicalize the path
+// doesn't have to exist, whatever part of it is missing is taken as written
+fn resolve(path: &Path, depth: &mut u32) -> io::Result<PathBuf> {
+    let mut real = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => real.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                real.pop();
+            }
+            Component::Normal(part) => {
+                real.push(part);
+                let is_link = real
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                if is_link {
+                    *depth += 1;
+                    if *depth > MAX_LINK_DEPTH {
+                        return Err(io::Error::other("too many levels of symlinks"));
+                    }
+                    let target = fs::read_link(&real)?;
+                    real.pop();
+                    real = resolve(&real.join(target), depth)?;
+                }
+            }
+        }
+    }
+    Ok(real)
+}
+
+// a file or symlink in the way of an entry is replaced rather than written through
+fn remove_existing(out: &Path) -> Result<()> {
+    if out
+        .symlink_metadata()
+        .is_ok_and(|metadata| !metadata.is_dir())
+    {
+        fs::remove_file(out)?;
+    }
+    Ok(())
+}
+
+pub struct Guard<'a> {
+    // canonical, so resolved paths can be compared against it
+    dest: PathBuf,
+    settings: &'a ExtractSettings,
+    size: u64,
+    entries: u64,
+    // every symlink made so far and the entry it came from, they are checked again once the
+    // whole archive is out since later entries can change what they lead to
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl<'a> Guard<'a> {
+    pub fn new(dest: &Path, settings: &'a ExtractSettings) -> Result<Self> {
+        Ok(Guard {
+            dest: dest
+                .canonicalize()
+                .context(format!("failed to resolve {}", dest.display()))?,
+            settings,
+            size: 0,
+            entries: 0,
+            links: vec![],
+        })
+    }
+
+    fn inside(&self, path: &Path) -> bool {
+        resolve(path, &mut 0).is_ok_and(|real| real.starts_with(&self.dest))
+    }
+
+    // called for every entry of the archive, skipped ones included
+    pub fn count(&mut self, path: &Path) -> Result<()> {
+        self.entries += 1;
+        let max = self.settings.max_entries;
+        if max != 0 && self.entries > max {
+            return Err(anyhow!(
+                "{} is past the limit of {max} entries per archive, raise extract.max_entries in the user config if that many are expected",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    // adds size bytes of path to what the archive has unpacked to so far
+    pub fn reserve(&mut self, path: &Path, size: u64) -> Result<()> {
+        self.size = self.size.saturating_add(size);
+        let max = self.settings.max_size;
+        if max != 0 && self.size > max {
+            return Err(anyhow!(
+                "{} takes the archive past the limit of {max} unpacked bytes, raise extract.max_size in the user config if it is expected",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    // checks an entry that is about to be unpacked to relative and returns where it goes, its
+    // parent directories are created on the way
+    pub fn place(
+        &self,
+        path: &Path,
+        relative: &Path,
+        kind: Kind,
+        mode: Option<u32>,
+    ) -> Result<PathBuf> {
+        match kind {
+            Kind::Device => return Err(anyhow!("{} is a device node", path.display())),
+            Kind::Fifo => return Err(anyhow!("{} is a fifo or socket", path.display())),
+            Kind::File | Kind::HardLink if mode.unwrap_or_default() & SETID != 0 => {
+                return Err(anyhow!("{} is setuid or setgid", path.display()))
+            }
+            _ => {}
+        }
+        let out = self.dest.join(relative);
+        let parent = out.parent().unwrap_or(&self.dest);
+        if !self.inside(parent) {
+            return Err(anyhow!(
+                "{} would be written outside the archive through a symlink",
+                path.display()
+            ));
+        }
+        create_dir_all(parent).with_context(|| format!("failed to unpack {}", path.display()))?;
+        Ok(out)
+    }
+
+    // writes the contents of a file entry to out, at most whatever is left of max_size
+    pub fn write(
+        &mut self,
+        path: &Path,
+        reader: &mut dyn Read,
+        out: &Path,
+        mode: Option<u32>,
+    ) -> Result<()> {
+        remove_existing(out)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(out)
+            .with_context(|| format!("failed to unpack {}", path.display()))?;
+        let left = match self.settings.max_size {
+            0 => u64::MAX,
+            max => max.saturating_sub(self.size),
+        };
+        let written = io::copy(&mut reader.take(left.saturating_add(1)), &mut file)
+            .context(format!("failed to extract {}", path.display()))?;
+        self.reserve(path, written)?;
+        if let Some(mode) = mode {
+            file.set_permissions(fs::Permissions::from_mode(mode & PERMISSIONS))?;
+        }
+        Ok(())
+    }
+
+    fn check_link(&self, path: &Path, out: &Path, target: &Path) -> Result<()> {
+        if target.is_absolute() {
+            return Err(anyhow!(
+                "{} is a symlink to the absolute path {}",
+                path.display(),
+                target.display()
+            ));
+        }
+        let parent = out.parent().unwrap_or(&self.dest);
+        if !self.inside(&parent.join(target)) {
+            return Err(anyhow!(
+                "{} is a symlink to {}, which is outside the archive",
+                path.display(),
+                target.display()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn symlink(&mut self, path: &Path, target: &Path, out: &Path) -> Result<()> {
+        self.check_link(path, out, target)?;
+        remove_existing(out)?;
+        symlink(target, out).context(format!("failed to extract {}", path.display()))?;
+        self.links.push((out.to_owned(), path.to_owned()));
+        Ok(())
+    }
+
+    // source is relative to the destination like every other entry
+    pub fn hard_link(&self, path: &Path, source: &Path, out: &Path) -> Result<()> {
+        let source = self.dest.join(source);
+        if !self.inside(&source) {
+            return Err(anyhow!(
+                "{} is a hard link to {}, which is outside the archive",
+                path.display(),
+                source.display()
+            ));
+        }
+        remove_existing(out)?;
+        fs::hard_link(&source, out).context(format!("failed to extract {}", path.display()))
+    }
+
+    // directories get their permissions last, by then a later entry may have put a symlink
+    // where one was
+    pub fn directory(&self, path: &Path, out: &Path) -> Result<()> {
+        if out
+            .symlink_metadata()
+            .is_ok_and(|metadata| !metadata.is_dir())
+        {
+            return Err(anyhow!(
+                "{} was replaced by a later entry of the archive",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn set_directory_mode(&self, path: &Path, out: &Path, mode: Option<u32>) -> Result<()> {
+        self.directory(path, out)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(out, fs::Permissions::from_mode(mode & PERMISSIONS))?;
+        }
+        Ok(())
+    }
+
+    // checks every symlink again now that nothing else will change underneath them, a link
+    // leading outside is removed before the error is returned
+    pub fn finish(&self) -> Result<()> {
+        for (out, path) in &self.links {
+            let target = fs::read_link(out)?;
+            if let Err(e) = self.check_link(path, out, &target) {
+                fs::remove_file(out)?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::extract::{unpack_tar, ArchiveFilter};
+    use super::*;
+    use std::{env, process};
+    use tar::{Builder, EntryType, Header};
+
+    // a scratch directory holding dest to unpack into and outside next to it, removed on drop
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let root = env::temp_dir().join(format!("faebuild-guard-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&root);
+            create_dir_all(root.join("dest")).unwrap();
+            create_dir_all(root.join("outside")).unwrap();
+            fs::write(root.join("outside/secret"), "secret").unwrap();
+            Scratch(root)
+        }
+
+        fn dest(&self) -> PathBuf {
+            self.0.join("dest")
+        }
+
+        fn outside(&self) -> PathBuf {
+            self.0.join("outside")
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    struct Entry<'a> {
+        path: &'a str,
+        kind: EntryType,
+        link: &'a str,
+        mode: u32,
+        data: &'a [u8],
+    }
+
+    fn file<'a>(path: &'a str, data: &'a [u8]) -> Entry<'a> {
+        Entry {
+            path,
+            kind: EntryType::Regular,
+            link: "",
+            mode: 0o644,
+            data,
+        }
+    }
+
+    fn link<'a>(kind: EntryType, path: &'a str, target: &'a str) -> Entry<'a> {
+        Entry {
+            path,
+            kind,
+            link: target,
+            mode: 0o777,
+            data: b"",
+        }
+    }
+
+    // the names are copied in as they are, tar::Builder would refuse to write the bad ones
+    fn archive(entries: &[Entry]) -> Vec<u8> {
+        let mut builder = Builder::new(vec![]);
+        for entry in entries {
+            let mut header = Header::new_old();
+            let old = header.as_old_mut();
+            old.name[..entry.path.len()].copy_from_slice(entry.path.as_bytes());
+            old.linkname[..entry.link.len()].copy_from_slice(entry.link.as_bytes());
+            header.set_entry_type(entry.kind);
+            header.set_mode(entry.mode);
+            header.set_size(entry.data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, entry.data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn unpack(scratch: &Scratch, entries: &[Entry], settings: &ExtractSettings) -> Result<()> {
+        let mut guard = Guard::new(&scratch.dest(), settings)?;
+        let filter = ArchiveFilter::new(0, &[], &[])?;
+        unpack_tar(archive(entries).as_slice(), &mut guard, &filter)?;
+        guard.finish()
+    }
+
+    // unpacks entries and checks the error says why and nothing outside dest was touched
+    fn refused(name: &str, entries: &[Entry], settings: &ExtractSettings, why: &str) -> Scratch {
+        let scratch = Scratch::new(name);
+        let error = format!("{:#}", unpack(&scratch, entries, settings).unwrap_err());
+        assert!(error.contains(why), "{name}: {error}");
+        assert_eq!(
+            fs::read_to_string(scratch.outside().join("secret")).unwrap(),
+            "secret"
+        );
+        assert!(!scratch.outside().join("evil").exists());
+        scratch
+    }
+
+    #[test]
+    fn unpacks_a_plain_archive() {
+        let scratch = Scratch::new("plain");
+        let entries = [
+            file("dir/a", b"a"),
+            link(EntryType::Symlink, "dir/b", "a"),
+            link(EntryType::Link, "c", "dir/a"),
+        ];
+        unpack(&scratch, &entries, &ExtractSettings::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(scratch.dest().join("dir/b")).unwrap(),
+            "a"
+        );
+        assert_eq!(fs::read_to_string(scratch.dest().join("c")).unwrap(), "a");
+    }
+
+    #[test]
+    fn refuses_paths_outside() {
+        let settings = ExtractSettings::default();
+        let why = "points outside the archive";
+        refused(
+            "dotdot",
+            &[file("../outside/evil", b"evil")],
+            &settings,
+            why,
+        );
+        refused("absolute", &[file("/tmp/evil", b"evil")], &settings, why);
+    }
+
+    #[test]
+    fn refuses_symlinks_outside() {
+        let settings = ExtractSettings::default();
+        refused(
+            "symlink",
+            &[link(EntryType::Symlink, "out", "../outside")],
+            &settings,
+            "is a symlink to ../outside, which is outside the archive",
+        );
+        refused(
+            "symlink-absolute",
+            &[link(EntryType::Symlink, "out", "/")],
+            &settings,
+            "is a symlink to the absolute path /",
+        );
+        // each link stays inside when it is made, followed one after another they lead out
+        refused(
+            "symlink-chain",
+            &[
+                link(EntryType::Symlink, "a", "b"),
+                link(EntryType::Symlink, "b", "c/.."),
+                link(EntryType::Symlink, "c", "."),
+                file("a/outside/evil", b"evil"),
+            ],
+            &settings,
+            "would be written outside the archive through a symlink",
+        );
+        refused(
+            "symlink-loop",
+            &[
+                link(EntryType::Symlink, "a", "b"),
+                link(EntryType::Symlink, "b", "a"),
+                file("a/evil", b"evil"),
+            ],
+            &settings,
+            "would be written outside the archive through a symlink",
+        );
+    }
+
+    #[test]
+    fn refuses_symlinks_changed_by_later_entries() {
+        // up is inside while there is nothing at here, not once here is a link to dest
+        let scratch = refused(
+            "symlink-later",
+            &[
+                link(EntryType::Symlink, "up", "here/.."),
+                link(EntryType::Symlink, "here", "."),
+            ],
+            &ExtractSettings::default(),
+            "is a symlink to here/.., which is outside the archive",
+        );
+        assert!(scratch.dest().join("up").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn refuses_hard_links_outside() {
+        let settings = ExtractSettings::default();
+        let why = "which is outside the archive";
+        let scratch = refused(
+            "hardlink",
+            &[link(EntryType::Link, "evil", "../outside/secret")],
+            &settings,
+            why,
+        );
+        assert!(!scratch.dest().join("evil").exists());
+        refused(
+            "hardlink-absolute",
+            &[link(EntryType::Link, "evil", "/etc/passwd")],
+            &settings,
+            why,
+        );
+        refused(
+            "hardlink-symlink",
+            &[
+                link(EntryType::Symlink, "out", "sub/.."),
+                link(EntryType::Symlink, "sub", "."),
+                link(EntryType::Link, "evil", "out/outside/secret"),
+            ],
+            &settings,
+            why,
+        );
+    }
+
+    #[test]
+    fn refuses_setid_and_special_files() {
+        let settings = ExtractSettings::default();
+        let mut setuid = file("setuid", b"#!/bin/sh");
+        setuid.mode = 0o4755;
+        refused("setuid", &[setuid], &settings, "is setuid or setgid");
+        let mut setgid = file("setgid", b"#!/bin/sh");
+        setgid.mode = 0o2755;
+        refused("setgid", &[setgid], &settings, "is setuid or setgid");
+        let device = "is a device node";
+        refused(
+            "char",
+            &[link(EntryType::Char, "null", "")],
+            &settings,
+            device,
+        );
+        refused(
+            "block",
+            &[link(EntryType::Block, "sda", "")],
+            &settings,
+            device,
+        );
+        refused(
+            "fifo",
+            &[link(EntryType::Fifo, "fifo", "")],
+            &settings,
+            "is a fifo or socket",
+        );
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let size = ExtractSettings {
+            max_size: 10,
+            max_entries: 0,
+        };
+        refused(
+            "size",
+            &[file("a", b"12345"), file("b", b"123456")],
+            &size,
+            "b takes the archive past the limit of 10 unpacked bytes",
+        );
+        let entries = ExtractSettings {
+            max_size: 0,
+            max_entries: 2,
+        };
+        refused(
+            "entries",
+            &[file("a", b""), file("b", b""), file("c", b"")],
+            &entries,
+            "c is past the limit of 2 entries per archive",
+        );
+        let scratch = Scratch::new("limits-exact");
+        unpack(&scratch, &[file("a", b"12345"), file("b", b"12345")], &size).unwrap();
+        unpack(&scratch, &[file("a", b""), file("b", b"")], &entries).unwrap();
+    }
+}
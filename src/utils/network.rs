@@ -0,0 +1,321 @@
+//this holds the http client and git settings every fetch goes through, it applies the proxy, extra certificates and per host credentials from the user config
+use crate::userconfig::{DownloadSettings, HostAuth, UserConfig};
+use anyhow::{anyhow, Context, Result};
+use git2::{Cred, FetchOptions, ProxyOptions, RemoteCallbacks};
+use percent_encoding::percent_decode_str;
+use reqwest::{redirect::Policy, tls::Certificate, Client, NoProxy, Proxy, RequestBuilder};
+use std::{
+    env,
+    ffi::CString,
+    fmt,
+    fs::{read, read_to_string},
+    os::{raw::c_int, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    ptr,
+};
+use url::Url;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Clone)]
+enum Credentials {
+    Token(String),
+    Basic { username: String, password: String },
+}
+
+// keeps secrets out of debug output
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Token(_) => write!(f, "Token"),
+            Credentials::Basic { username, .. } => write!(f, "Basic({username})"),
+        }
+    }
+}
+
+/// one client is shared by every download so connections and tls sessions are reused
+#[derive(Debug, Default, Clone)]
+pub struct Network {
+    client: Client,
+    proxy: Option<Url>,
+    no_proxy: Vec<String>,
+    // in the order they are looked at, the user config comes before netrc
+    credentials: Vec<(String, Credentials)>,
+}
+
+fn credentials_of(auth: &HostAuth) -> Result<Credentials> {
+    match (&auth.token, &auth.username, &auth.password) {
+        (Some(token), None, None) => Ok(Credentials::Token(token.clone())),
+        (None, Some(username), Some(password)) => Ok(Credentials::Basic {
+            username: username.clone(),
+            password: password.clone(),
+        }),
+        _ => Err(anyhow!(
+            "auth for {} needs either a token or a username and password",
+            auth.host
+        )),
+    }
+}
+
+fn default_netrc() -> Option<PathBuf> {
+    if let Some(netrc) = env::var_os("NETRC").filter(|netrc| !netrc.is_empty()) {
+        return Some(PathBuf::from(netrc));
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc"))
+}
+
+// the machine entries of a netrc file. default entries are ignored, they would hand the same
+// password to every host we ever download from. parsing stops at the first macdef since macro
+// bodies aren't tokens
+fn parse_netrc(text: &str) -> Vec<(String, Credentials)> {
+    let mut entries = vec![];
+    let mut tokens = text.split_whitespace();
+    let mut machine: Option<(String, Option<String>, Option<String>)> = None;
+    let mut finish = |machine: Option<(String, Option<String>, Option<String>)>| {
+        if let Some((host, Some(username), Some(password))) = machine {
+            entries.push((host, Credentials::Basic { username, password }));
+        }
+    };
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                finish(machine.take());
+                machine = tokens.next().map(|host| (host.to_owned(), None, None));
+            }
+            "default" => finish(machine.take()),
+            "login" => {
+                let login = tokens.next().map(str::to_owned);
+                if let Some(machine) = &mut machine {
+                    machine.1 = login;
+                }
+            }
+            "password" => {
+                let password = tokens.next().map(str::to_owned);
+                if let Some(machine) = &mut machine {
+                    machine.2 = password;
+                }
+            }
+            "account" => {
+                tokens.next();
+            }
+            "macdef" => break,
+            _ => {}
+        }
+    }
+    finish(machine);
+    entries
+}
+
+// host is either a bare host name or host:port
+fn host_matches(host: &str, url: &Url) -> bool {
+    let Some(url_host) = url.host_str() else {
+        return false;
+    };
+    match url.port() {
+        Some(port) => host == format!("{url_host}:{port}") || host == url_host,
+        None => host == url_host,
+    }
+}
+
+// the certificates of a pem bundle one by one, native-tls only reads the first one it is given
+fn split_pem(pem: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(pem);
+    text.split_inclusive(PEM_END)
+        .filter_map(|block| block.find(PEM_BEGIN).map(|start| block[start..].to_owned()))
+        .collect()
+}
+
+// libgit2 keeps its certificates globally, the bundle is loaded on top of the system ones
+fn add_git_ca_bundle(path: &Path) -> Result<()> {
+    let file = CString::new(path.as_os_str().as_bytes())?;
+    libgit2_sys::init();
+    // SAFETY: both pointers outlive the call, libgit2 copies the paths it is given
+    let result = unsafe {
+        libgit2_sys::git_libgit2_opts(
+            libgit2_sys::GIT_OPT_SET_SSL_CERT_LOCATIONS as c_int,
+            file.as_ptr(),
+            ptr::null::<std::os::raw::c_char>(),
+        )
+    };
+    if result < 0 {
+        return Err(anyhow!(
+            "git failed to load certificates from {}: {}",
+            path.display(),
+            git2::Error::last_error(result)
+                .map(|e| e.message().to_owned())
+                .unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+// libgit2-sys doesn't name the timeout options yet, these are their values in git_libgit2_opt_t
+// since libgit2 1.7 added them after GIT_OPT_SET_OWNER_VALIDATION and GET/SET_HOMEDIR
+const GIT_OPT_SET_SERVER_CONNECT_TIMEOUT: c_int = 39;
+const GIT_OPT_SET_SERVER_TIMEOUT: c_int = 41;
+const _: () = assert!(
+    libgit2_sys::GIT_OPT_SET_OWNER_VALIDATION as c_int == 36,
+    "libgit2-sys renumbered git_libgit2_opt_t, check the timeout options"
+);
+
+// libgit2 keeps its timeouts globally as well, in milliseconds with 0 for its defaults. a system
+// libgit2 older than 1.7 doesn't have them and keeps its own defaults
+fn set_git_timeouts(settings: &DownloadSettings) -> Result<()> {
+    if git2::Version::get().libgit2_version() < (1, 7, 0) {
+        return Ok(());
+    }
+    let options = [
+        (
+            "connect",
+            GIT_OPT_SET_SERVER_CONNECT_TIMEOUT,
+            settings.connect_timeout,
+        ),
+        ("read", GIT_OPT_SET_SERVER_TIMEOUT, settings.read_timeout),
+    ];
+    libgit2_sys::init();
+    for (name, option, seconds) in options {
+        let milliseconds = c_int::try_from(seconds * 1000).unwrap_or(c_int::MAX);
+        // SAFETY: both options take a single int
+        let result = unsafe { libgit2_sys::git_libgit2_opts(option, milliseconds) };
+        if result < 0 {
+            return Err(anyhow!("git failed to set the {name} timeout"));
+        }
+    }
+    Ok(())
+}
+
+impl Network {
+    /// the client and git settings for the network section of config, the timeouts come from
+    /// its download section
+    pub fn new(config: &UserConfig) -> Result<Network> {
+        let settings = &config.network;
+        let mut builder = Client::builder().redirect(Policy::limited(10));
+        if let Some(timeout) = DownloadSettings::duration(config.download.connect_timeout) {
+            builder = builder.connect_timeout(timeout);
+        }
+        set_git_timeouts(&config.download)?;
+        if let Some(proxy) = &settings.proxy {
+            let no_proxy = NoProxy::from_string(&settings.no_proxy.join(","));
+            builder = builder.proxy(Proxy::all(proxy.clone())?.no_proxy(no_proxy));
+        }
+        if let Some(ca_bundle) = &settings.ca_bundle {
+            let pem = read(ca_bundle).context(format!("failed to read {}", ca_bundle.display()))?;
+            for certificate in split_pem(&pem) {
+                let certificate = Certificate::from_pem(certificate.as_bytes())
+                    .context(format!("failed to parse {}", ca_bundle.display()))?;
+                builder = builder.add_root_certificate(certificate);
+            }
+            add_git_ca_bundle(ca_bundle)?;
+        }
+
+        let mut credentials = vec![];
+        for auth in &settings.auth {
+            credentials.push((auth.host.clone(), credentials_of(auth)?));
+        }
+        // an explicitly configured netrc has to exist, the default one is optional
+        let netrc = match &settings.netrc {
+            Some(netrc) => {
+                Some(read_to_string(netrc).context(format!("failed to read {}", netrc.display()))?)
+            }
+            None => default_netrc().and_then(|netrc| read_to_string(netrc).ok()),
+        };
+        if let Some(netrc) = netrc {
+            credentials.extend(parse_netrc(&netrc));
+        }
+
+        Ok(Network {
+            client: builder.build()?,
+            proxy: settings.proxy.clone(),
+            no_proxy: settings.no_proxy.clone(),
+            credentials,
+        })
+    }
+
+    fn credentials(&self, url: &Url) -> Option<&Credentials> {
+        self.credentials
+            .iter()
+            .find(|(host, _)| host_matches(host, url))
+            .map(|(_, credentials)| credentials)
+    }
+
+    /// what an ftp url logs in with, credentials in the url come first, then the user config
+    /// and netrc, and anonymous when there are none
+    pub fn ftp_login(&self, url: &Url) -> (String, String) {
+        let decode = |part: &str| percent_decode_str(part).decode_utf8_lossy().into_owned();
+        if !url.username().is_empty() {
+            return (
+                decode(url.username()),
+                decode(url.password().unwrap_or_default()),
+            );
+        }
+        match self.credentials(url) {
+            Some(Credentials::Basic { username, password }) => (username.clone(), password.clone()),
+            _ => ("anonymous".to_owned(), "anonymous@".to_owned()),
+        }
+    }
+
+    fn bypasses_proxy(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default();
+        self.no_proxy.iter().any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            entry == "*" || host == domain || host.ends_with(&format!(".{domain}"))
+        })
+    }
+
+    /// a get request for url carrying whatever credentials the user set for its host, reqwest
+    /// drops them again if the request is redirected to another host
+    pub fn get(&self, url: &Url) -> RequestBuilder {
+        let request = self.client.get(url.clone());
+        match self.credentials(url) {
+            Some(Credentials::Token(token)) => request.bearer_auth(token),
+            Some(Credentials::Basic { username, password }) => {
+                request.basic_auth(username, Some(password))
+            }
+            None => request,
+        }
+    }
+
+    /// fetch options for url with the proxy and credentials applied, the callbacks are set on
+    /// top of these by the caller
+    pub fn git_fetch_options<'a>(
+        &'a self,
+        url: &str,
+        mut callbacks: RemoteCallbacks<'a>,
+    ) -> FetchOptions<'a> {
+        let mut options = FetchOptions::new();
+        let parsed = Url::parse(url).ok();
+
+        let mut proxy = ProxyOptions::new();
+        match (&self.proxy, &parsed) {
+            (Some(url), Some(parsed)) if !self.bypasses_proxy(parsed) => {
+                proxy.url(url.as_str());
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                proxy.auto();
+            }
+        }
+        options.proxy_options(proxy);
+
+        match parsed.as_ref().and_then(|parsed| self.credentials(parsed)) {
+            Some(Credentials::Token(token)) => {
+                options.custom_headers(&[&format!("Authorization: Bearer {token}")]);
+            }
+            Some(Credentials::Basic { username, password }) => {
+                let mut tried = false;
+                callbacks.credentials(move |_, _, _| {
+                    // libgit2 keeps asking as long as we answer, one rejected attempt is enough
+                    if tried {
+                        return Err(git2::Error::from_str("the credentials were rejected"));
+                    }
+                    tried = true;
+                    Cred::userpass_plaintext(username, password)
+                });
+            }
+            None => {}
+        }
+        options.remote_callbacks(callbacks);
+        options
+    }
+}
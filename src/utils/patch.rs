@@ -1,24 +1,30 @@
-use anyhow::{anyhow, Result};
-use std::{env::set_current_dir, path::PathBuf, process::Command};
+use crate::{
+    error::{Error, Result},
+    progress::Progress,
+};
+use anyhow::anyhow;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-pub fn patch(patches: Vec<PathBuf>, workdir: &PathBuf) -> Result<()> {
-    set_current_dir(workdir)?;
+/// applies each of patches in order with patch -p1 inside workdir, stopping at the first that
+/// fails
+pub fn patch(patches: Vec<PathBuf>, workdir: &Path, progress: &dyn Progress) -> Result<()> {
     for patch in patches {
-        print!("Applying {}", &patch.display());
-        match Command::new("sh")
-            .args(["-c", &format!("patch -p1 < {}", &patch.display())])
+        progress.message(&format!("Applying {}", patch.display()));
+        let cause = match Command::new("patch")
+            .arg("-p1")
+            .arg("-i")
+            .arg(&patch)
+            .current_dir(workdir)
             .status()
         {
-            Ok(status) => {
-                if !status.success() {
-                    return Err(anyhow!("failed to apply {}", patch.display()));
-                }
-            }
-            Err(e) => {
-                eprintln!("{e}");
-                return Err(anyhow!("failed to apply {}", patch.display()));
-            }
-        }
+            Ok(status) if status.success() => continue,
+            Ok(status) => anyhow!("patch exited with {status}"),
+            Err(e) => anyhow!(e).context("failed to run patch"),
+        };
+        return Err(Error::Patch { patch, cause });
     }
     Ok(())
 }
@@ -0,0 +1,374 @@
+//this verifies openpgp, ssh and minisign signatures against the keys a recipe pins, openpgp keys listed in validpgpkeys are read from keys/pgp, ssh keys from keys/ssh/allowed_signers and minisign keys are given inline
+use anyhow::{anyhow, Context, Result};
+use minisign_verify::Error as MinisignError;
+use pgp::{
+    composed::{Deserializable, DetachedSignature, SignedPublicKey},
+    types::{KeyDetails, KeyId},
+};
+use ssh_key::{HashAlg, PublicKey, SshSig};
+use std::{
+    fmt,
+    fs::{self, read_to_string, File},
+    io::{self, BufReader, Cursor, Read},
+    path::Path,
+};
+
+// files are read in chunks this big while they are verified
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// the namespace git signs commits and tags in
+const GIT_NAMESPACE: &str = "git";
+/// how the armored signatures git stores on commits and tags begin
+pub const PGP_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+/// see [`PGP_SIGNATURE`], for ssh signatures
+pub const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----";
+
+/// the keys a recipe trusts, only keys listed in validpgpkeys are ever loaded
+#[derive(Debug, Default)]
+pub struct Keyring {
+    keys: Vec<SignedPublicKey>,
+    signers: Vec<AllowedSigner>,
+}
+
+// an entry of an ssh allowed signers file, see ssh-keygen(1)
+#[derive(Debug)]
+struct AllowedSigner {
+    principals: String,
+    namespaces: Option<Vec<String>>,
+    key: PublicKey,
+}
+
+/// who made a signature that verified
+#[derive(Debug)]
+pub struct Signer {
+    /// the fingerprint of the key that made it
+    pub fingerprint: String,
+    /// the user id of an openpgp key or the principal of an ssh key
+    pub user: Option<String>,
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{user} ({})", self.fingerprint),
+            None => write!(f, "{}", self.fingerprint),
+        }
+    }
+}
+
+/// fingerprints are compared without spaces and in upper case, the way gpg prints them varies
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+fn fingerprint_of(key: &(impl KeyDetails + ?Sized)) -> String {
+    format!("{:X}", key.fingerprint())
+}
+
+// loads the keys for fingerprints out of every file in dir, each file may be armored or binary
+// and hold several keys
+fn load_pgp(dir: &Path, fingerprints: &[String]) -> Result<Vec<SignedPublicKey>> {
+    if fingerprints.is_empty() {
+        return Ok(vec![]);
+    }
+    if !dir.is_dir() {
+        return Err(anyhow!(
+            "validpgpkeys is set but there is no keyring at {}",
+            dir.display()
+        ));
+    }
+    let wanted: Vec<String> = fingerprints
+        .iter()
+        .map(|fingerprint| normalize_fingerprint(fingerprint))
+        .collect();
+
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    files.sort();
+    let mut keys: Vec<SignedPublicKey> = vec![];
+    for file in files.iter().filter(|file| file.is_file()) {
+        let reader = BufReader::new(File::open(file)?);
+        let (parsed, _) = SignedPublicKey::from_reader_many(reader)
+            .context(format!("failed to read keys from {}", file.display()))?;
+        for key in parsed {
+            let key = key.context(format!("failed to read keys from {}", file.display()))?;
+            let fingerprint = fingerprint_of(&key);
+            if !wanted.contains(&fingerprint)
+                || keys
+                    .iter()
+                    .any(|known| fingerprint_of(known) == fingerprint)
+            {
+                continue;
+            }
+            key.verify_bindings().context(format!(
+                "key {fingerprint} in {} has invalid self signatures",
+                file.display()
+            ))?;
+            keys.push(key);
+        }
+    }
+
+    for fingerprint in &wanted {
+        if !keys.iter().any(|key| &fingerprint_of(key) == fingerprint) {
+            return Err(anyhow!(
+                "validpgpkeys lists {fingerprint} but no key for it is in {}",
+                dir.display()
+            ));
+        }
+    }
+    Ok(keys)
+}
+
+fn is_key_type(field: &str) -> bool {
+    ["ssh-", "ecdsa-", "sk-"]
+        .iter()
+        .any(|prefix| field.starts_with(prefix))
+}
+
+// splits an options field on the commas that aren't inside quotes
+fn split_options(options: &str) -> Vec<&str> {
+    let mut split = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in options.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                split.push(&options[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&options[start..]);
+    split
+}
+
+// each line is principals, optional options and a public key. of the options only namespaces
+// is honoured, a line with any other option is skipped rather than trusted more than meant
+fn load_allowed_signers(file: &Path) -> Result<Vec<AllowedSigner>> {
+    if !file.exists() {
+        return Ok(vec![]);
+    }
+    let text = read_to_string(file).context(format!("failed to read {}", file.display()))?;
+    let mut signers = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let principals = fields.next().unwrap_or_default().to_owned();
+        let mut field = fields.next();
+        let mut namespaces = None;
+        let mut unsupported = false;
+        if let Some(options) = field.filter(|field| !is_key_type(field)) {
+            for option in split_options(options) {
+                match option.strip_prefix("namespaces=") {
+                    Some(value) => {
+                        namespaces = Some(
+                            value
+                                .trim_matches('"')
+                                .split(',')
+                                .map(str::to_owned)
+                                .collect(),
+                        )
+                    }
+                    None => unsupported = true,
+                }
+            }
+            field = fields.next();
+        }
+        let (Some(kind), Some(data)) = (field, fields.next()) else {
+            return Err(anyhow!(
+                "{}:{}: missing public key",
+                file.display(),
+                number + 1
+            ));
+        };
+        let key = PublicKey::from_openssh(&format!("{kind} {data}")).context(format!(
+            "{}:{}: invalid public key",
+            file.display(),
+            number + 1
+        ))?;
+        if !unsupported {
+            signers.push(AllowedSigner {
+                principals,
+                namespaces,
+                key,
+            });
+        }
+    }
+    Ok(signers)
+}
+
+impl Keyring {
+    /// loads the keys under dir, normally keys/ next to the recipe
+    pub fn load(dir: &Path, fingerprints: &[String]) -> Result<Keyring> {
+        Ok(Keyring {
+            keys: load_pgp(&dir.join("pgp"), fingerprints)?,
+            signers: load_allowed_signers(&dir.join("ssh").join("allowed_signers"))?,
+        })
+    }
+
+    /// checks the detached signature in signature over the contents of data
+    pub fn verify(&self, data: &Path, signature: &[u8]) -> Result<Signer> {
+        self.verify_pgp(
+            || File::open(data).map(BufReader::new),
+            &data.display().to_string(),
+            signature,
+        )
+    }
+
+    /// checks a signature git stored on a commit or tag, which may be openpgp or ssh
+    pub fn verify_git(&self, what: &str, data: &[u8], signature: &[u8]) -> Result<Signer> {
+        if signature.starts_with(SSH_SIGNATURE.as_bytes()) {
+            return self.verify_ssh(what, data, signature);
+        }
+        self.verify_pgp(|| Ok(Cursor::new(data)), what, signature)
+    }
+
+    // any one signature made by a trusted key or one of its subkeys is enough, open is called
+    // again for every signature that has to be checked
+    fn verify_pgp<R: Read>(
+        &self,
+        open: impl Fn() -> io::Result<R>,
+        what: &str,
+        signature: &[u8],
+    ) -> Result<Signer> {
+        let (signatures, _) = DetachedSignature::from_reader_many(Cursor::new(signature))
+            .context("failed to parse the signature")?;
+        let mut problems = vec![];
+        for signature in signatures {
+            let signature = signature
+                .context("failed to parse the signature")?
+                .signature;
+            let issuers: Vec<String> = signature
+                .issuer_fingerprint()
+                .iter()
+                .map(|fingerprint| format!("{fingerprint:X}"))
+                .collect();
+            let key_ids: Vec<KeyId> = signature.issuer_key_id().into_iter().copied().collect();
+            let issued = |key: &dyn KeyDetails| {
+                issuers.contains(&fingerprint_of(key)) || key_ids.contains(&key.legacy_key_id())
+            };
+
+            let Some(key) = self.keys.iter().find(|key| {
+                issued(&key.primary_key) || key.public_subkeys.iter().any(|sub| issued(&sub.key))
+            }) else {
+                let issuer = issuers
+                    .first()
+                    .cloned()
+                    .or(key_ids.first().map(|id| format!("{id:?}")))
+                    .unwrap_or("an unknown key".to_owned());
+                problems.push(format!("signed by {issuer}, which is not in validpgpkeys"));
+                continue;
+            };
+
+            let verified = if issued(&key.primary_key) {
+                signature.verify(key, open()?)
+            } else {
+                match key.public_subkeys.iter().find(|sub| issued(&sub.key)) {
+                    Some(sub) => signature.verify(sub, open()?),
+                    None => continue,
+                }
+            };
+            match verified {
+                Ok(()) => {
+                    return Ok(Signer {
+                        fingerprint: fingerprint_of(key),
+                        user: key
+                            .details
+                            .users
+                            .first()
+                            .and_then(|user| user.id.as_str())
+                            .map(str::to_owned),
+                    })
+                }
+                // the library only says which internal check failed, none of which is useful here
+                Err(_) => problems.push(format!(
+                    "the signature from {} does not match the file",
+                    fingerprint_of(key)
+                )),
+            }
+        }
+        if problems.is_empty() {
+            return Err(anyhow!("{what} has an empty signature"));
+        }
+        Err(anyhow!("failed to verify {what}: {}", problems.join(", ")))
+    }
+
+    fn verify_ssh(&self, what: &str, data: &[u8], signature: &[u8]) -> Result<Signer> {
+        let signature = SshSig::from_pem(signature).context("failed to parse the signature")?;
+        let fingerprint = |key: &PublicKey| key.fingerprint(HashAlg::Sha256).to_string();
+        let Some(signer) = self.signers.iter().find(|signer| {
+            signer.key.key_data() == signature.public_key()
+                && signer.namespaces.as_ref().is_none_or(|namespaces| {
+                    namespaces
+                        .iter()
+                        .any(|namespace| namespace == GIT_NAMESPACE)
+                })
+        }) else {
+            let key = PublicKey::from(signature.public_key().clone());
+            return Err(anyhow!(
+                "failed to verify {what}: signed by {}, which is not in keys/ssh/allowed_signers",
+                fingerprint(&key)
+            ));
+        };
+        signer
+            .key
+            .verify(GIT_NAMESPACE, data, &signature)
+            .map_err(|_| {
+                anyhow!(
+                    "failed to verify {what}: the signature from {} does not match",
+                    fingerprint(&signer.key)
+                )
+            })?;
+        Ok(Signer {
+            fingerprint: fingerprint(&signer.key),
+            user: Some(signer.principals.clone()),
+        })
+    }
+}
+
+/// parses the base64 public key minisign prints and keeps in .pub files
+pub fn minisign_key(public_key: &str) -> Result<minisign_verify::PublicKey> {
+    minisign_verify::PublicKey::from_base64(public_key.trim())
+        .map_err(|e| anyhow!("{public_key} is not a minisign public key: {e}"))
+}
+
+/// checks the minisign signature in signature over data, on success the trusted comment the
+/// signer attached comes back
+pub fn verify_minisign(data: &Path, public_key: &str, signature: &Path) -> Result<String> {
+    let key = minisign_key(public_key)?;
+    let text =
+        read_to_string(signature).context(format!("failed to read {}", signature.display()))?;
+    let signature = minisign_verify::Signature::decode(&text)
+        .map_err(|e| anyhow!("failed to parse {}: {e}", signature.display()))?;
+    let failed = |e: MinisignError| anyhow!("failed to verify {}: {e}", data.display());
+
+    let verified = match key.verify_stream(&signature) {
+        Ok(mut verifier) => {
+            let mut file = File::open(data)?;
+            let mut buffer = vec![0; CHUNK_SIZE];
+            loop {
+                let bytes_read = file.read(&mut buffer)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                verifier.update(&buffer[..bytes_read]);
+            }
+            verifier.finalize()
+        }
+        // signatures from old minisign releases sign the whole file rather than its hash
+        Err(MinisignError::UnsupportedLegacyMode) => key.verify(&fs::read(data)?, &signature, true),
+        Err(e) => Err(e),
+    };
+    verified.map_err(failed)?;
+    Ok(signature.trusted_comment().to_owned())
+}
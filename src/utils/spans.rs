@@ -0,0 +1,234 @@
+//this maps paths like sources[2].commit to where they sit in a yaml document, serde_yaml throws positions away once deserialization succeeds
+use std::{collections::HashMap, fmt};
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+/// a position in a yaml document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// starting at 1
+    pub line: usize,
+    /// in characters, starting at 1
+    pub col: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+impl Location {
+    /// byte offset of this location in text
+    pub fn offset(&self, text: &str) -> Option<usize> {
+        let line_start = if self.line == 1 {
+            0
+        } else {
+            text.match_indices('\n').nth(self.line - 2)?.0 + 1
+        };
+        let line = &text[line_start..];
+        let col = match line.char_indices().nth(self.col - 1) {
+            Some((col, _)) => col,
+            None => line.len(),
+        };
+        Some(line_start + col)
+    }
+}
+
+impl From<Marker> for Location {
+    fn from(marker: Marker) -> Self {
+        Location {
+            line: marker.line(),
+            col: marker.col() + 1,
+        }
+    }
+}
+
+enum Frame {
+    Map { path: String, key: Option<String> },
+    Seq { path: String, index: usize },
+}
+
+/// where every key and value of a yaml document starts, by path
+#[derive(Debug, Default)]
+pub struct Spans {
+    keys: HashMap<String, Location>,
+    values: HashMap<String, Location>,
+}
+
+struct Receiver {
+    spans: Spans,
+    stack: Vec<Frame>,
+}
+
+impl Receiver {
+    // path of the node that the next value event belongs to
+    fn value_path(&self) -> String {
+        match self.stack.last() {
+            None => String::new(),
+            Some(Frame::Map { path, key }) => join(path, key.as_deref().unwrap_or_default()),
+            Some(Frame::Seq { path, index }) => format!("{path}[{index}]"),
+        }
+    }
+
+    fn expecting_key(&self) -> bool {
+        matches!(self.stack.last(), Some(Frame::Map { key: None, .. }))
+    }
+
+    // moves the parent on to its next key or item once a value is complete
+    fn advance(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Map { key, .. }) => *key = None,
+            Some(Frame::Seq { index, .. }) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+impl MarkedEventReceiver for Receiver {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::Scalar(value, ..) if self.expecting_key() => {
+                if let Some(Frame::Map { path, key }) = self.stack.last_mut() {
+                    // block mappings are marked after their first key, so they start at that key
+                    self.spans
+                        .values
+                        .entry(path.clone())
+                        .or_insert(marker.into());
+                    self.spans.keys.insert(join(path, &value), marker.into());
+                    *key = Some(value);
+                }
+            }
+            Event::Scalar(..) | Event::Alias(_) => {
+                self.spans.values.insert(self.value_path(), marker.into());
+                self.advance();
+            }
+            Event::MappingStart(..) => {
+                let path = self.value_path();
+                self.stack.push(Frame::Map { path, key: None });
+            }
+            Event::SequenceStart(..) => {
+                let path = self.value_path();
+                self.spans.values.insert(path.clone(), marker.into());
+                self.stack.push(Frame::Seq { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.advance();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Spans {
+    /// documents that fail to parse yield no spans, serde_yaml reports those errors itself
+    pub fn parse(text: &str) -> Spans {
+        let mut receiver = Receiver {
+            spans: Spans::default(),
+            stack: vec![],
+        };
+        let _ = Parser::new_from_str(text).load(&mut receiver, false);
+        receiver.spans
+    }
+
+    /// where the key of the mapping entry at path starts
+    pub fn key(&self, path: &str) -> Option<Location> {
+        self.keys.get(path).copied()
+    }
+
+    /// where the value at path starts, a block mapping starts at its first key
+    pub fn value(&self, path: &str) -> Option<Location> {
+        self.values.get(path).copied()
+    }
+
+    /// best location for path, walking up to the closest parent that exists in the document
+    pub fn locate(&self, path: &str) -> Location {
+        let mut path = path;
+        loop {
+            if let Some(location) = self.key(path).or(self.value(path)) {
+                return location;
+            }
+            match path.rfind(['.', '[']) {
+                Some(i) => path = &path[..i],
+                None => return Location { line: 1, col: 1 },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPE: &str = "name: foo
+sources:
+  - type: file
+    path: a.patch
+  - {type: patch, path: b.patch}
+  - type: git
+    url: https://example.org/foo.git
+    commit: abc
+arch: [x86_64, aarch64]
+";
+
+    fn at(line: usize, col: usize) -> Option<Location> {
+        Some(Location { line, col })
+    }
+
+    #[test]
+    fn finds_keys_and_values_by_path() {
+        let spans = Spans::parse(RECIPE);
+        assert_eq!(spans.key("name"), at(1, 1));
+        assert_eq!(spans.value("name"), at(1, 7));
+        assert_eq!(spans.key("sources[2].commit"), at(8, 5));
+        assert_eq!(spans.value("sources[2].commit"), at(8, 13));
+        assert_eq!(spans.value("sources[2]"), at(6, 5));
+        assert_eq!(spans.key("sources[1].path"), at(5, 19));
+        assert_eq!(spans.value("arch[1]"), at(9, 16));
+        assert_eq!(spans.key("sources[2]"), None);
+        assert_eq!(spans.value("sources[3]"), None);
+    }
+
+    #[test]
+    fn locates_missing_paths_at_their_closest_parent() {
+        let spans = Spans::parse(RECIPE);
+        assert_eq!(
+            spans.locate("sources[2].commit"),
+            Location { line: 8, col: 5 }
+        );
+        assert_eq!(
+            spans.locate("sources[2].sha256sum"),
+            Location { line: 6, col: 5 }
+        );
+        assert_eq!(spans.locate("sources[5].url"), Location { line: 2, col: 1 });
+        assert_eq!(spans.locate("license"), Location { line: 1, col: 1 });
+    }
+
+    #[test]
+    fn yields_no_spans_for_broken_documents() {
+        let spans = Spans::parse("name: [foo\nsources:\n");
+        assert_eq!(spans.key("sources"), None);
+        assert_eq!(spans.locate("sources"), Location { line: 1, col: 1 });
+    }
+
+    #[test]
+    fn converts_locations_to_byte_offsets() {
+        let text = "name: fö\nrel: 1\n";
+        assert_eq!(Location { line: 1, col: 1 }.offset(text), Some(0));
+        assert_eq!(Location { line: 1, col: 9 }.offset(text), Some(9));
+        assert_eq!(Location { line: 2, col: 6 }.offset(text), Some(15));
+        assert_eq!(Location { line: 3, col: 1 }.offset(text), Some(text.len()));
+        assert_eq!(Location { line: 4, col: 1 }.offset(text), None);
+    }
+}